@@ -0,0 +1,117 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, TextureCreator};
+use sdl2::surface::Surface;
+use sdl2::video::WindowContext;
+
+use crate::config;
+use crate::theme::Theme;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "bmp", "gif"];
+const AUDIO_EXTENSIONS: [&str; 5] = ["ogg", "mp3", "wav", "flac", "oga"];
+
+// Words in file names that give away what an image is, like StepMania's *bg* and *bn*
+const BACKGROUND_HINTS: [&str; 2] = ["bg", "background"];
+const BANNER_HINTS: [&str; 2] = ["bn", "banner"];
+
+
+// Plenty of simfiles in the wild have an empty #BACKGROUND or #MUSIC, or point to a file
+// that was renamed or has a different case. These look for the file the tag meant in the song folder.
+
+// `banner` and `cdtitle` are the song's tags for those, their images are never taken for the background
+pub fn find_background(dir: &Path, tag: &str, banner: &str, cdtitle: &str) -> Option<PathBuf> {
+    if let Some(path) = find(dir, tag, &IMAGE_EXTENSIONS, &[]) {
+        return Some(path);
+    }
+
+    let others = [find_banner(dir, banner), find(dir, cdtitle, &IMAGE_EXTENSIONS, &[])];
+    let images: Vec<PathBuf> = files(dir, &IMAGE_EXTENSIONS).into_iter()
+        .filter(|path| !others.iter().flatten().any(|other| other == path) && !has_hint(path, &BANNER_HINTS))
+        .collect();
+    match images.iter().find(|path| has_hint(path, &BACKGROUND_HINTS)) {
+        Some(path) => Some(path.clone()),
+        // Songs with a single image left over use it as their background
+        None if images.len() == 1 => images.into_iter().next(),
+        None => None,
+    }
+}
+
+pub fn find_banner(dir: &Path, tag: &str) -> Option<PathBuf> {
+    find(dir, tag, &IMAGE_EXTENSIONS, &BANNER_HINTS)
+}
+
+// Any audio file will do, song folders only ever have the one
+pub fn find_music(dir: &Path, tag: &str) -> Option<PathBuf> {
+    find(dir, tag, &AUDIO_EXTENSIONS, &[]).or_else(|| files(dir, &AUDIO_EXTENSIONS).into_iter().next())
+}
+
+
+// The tag's file as written, then ignoring case, then with another extension, then one whose name has a hint
+fn find(dir: &Path, tag: &str, extensions: &[&str], hints: &[&str]) -> Option<PathBuf> {
+    let candidates = files(dir, extensions);
+
+    if !tag.is_empty() {
+        let path = dir.join(tag);
+        if path.is_file() {
+            return Some(path);
+        }
+
+        // Tags are often written on a case-insensitive file system, or for a file converted since
+        let lower = |part: Option<&OsStr>| part.map(|part| part.to_string_lossy().to_lowercase());
+        let tag = Path::new(tag);
+        let found = candidates.iter().find(|path| lower(path.file_name()) == lower(tag.file_name()))
+            .or_else(|| candidates.iter().find(|path| lower(path.file_stem()) == lower(tag.file_stem())));
+        if let Some(path) = found {
+            return Some(path.clone());
+        }
+    }
+
+    candidates.into_iter().find(|path| has_hint(path, hints))
+}
+
+
+// Files in `dir` with one of the extensions (any case), sorted so the pick doesn't change between runs
+fn files(dir: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e))))
+        .collect();
+    files.sort();
+    files
+}
+
+
+fn has_hint(path: &Path, hints: &[&str]) -> bool {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default();
+    hints.iter().any(|hint| stem.contains(hint))
+}
+
+
+// Stands in for a song without a background: a gradient from the theme's menu background
+// down to a dark color picked from the title, so songs without one still look apart
+pub fn generated_background<'a>(texture_creator: &'a TextureCreator<WindowContext>, theme: &Theme, title: &str) -> Texture<'a> {
+    const ROWS: u32 = 64;
+    let top = theme.menu_background;
+    let hash = config::content_hash(title.as_bytes());
+    let bottom = Color::RGB(40 + (hash & 0x3f) as u8, 40 + (hash >> 8 & 0x3f) as u8, 40 + (hash >> 16 & 0x3f) as u8);
+
+    // Scaling the texture up to the screen smooths the steps between rows
+    let mut surface = Surface::new(1, ROWS, PixelFormatEnum::RGB24).unwrap();
+    for row in 0..ROWS {
+        let t = row as f64 / (ROWS - 1) as f64;
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t) as u8;
+        let color = Color::RGB(mix(top.r, bottom.r), mix(top.g, bottom.g), mix(top.b, bottom.b));
+        surface.fill_rect(Rect::new(0, row as i32, 1, 1), color).unwrap();
+    }
+    texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string()).unwrap()
+}
@@ -0,0 +1,210 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use sdl2::mixer::{Channel, Chunk, Music};
+use sdl2::rwops::RWops;
+
+use crate::modifiers::Modifiers;
+
+// Mixer channel a decoded song plays on, reserved at startup so nothing else takes it
+pub const MUSIC_CHANNEL: Channel = Channel(0);
+
+// Time-stretch window and how far each window may move to line up with the previous one
+const STRETCH_WINDOW_MS: usize = 40;
+const STRETCH_TOLERANCE_MS: usize = 10;
+// Windows are lined up on the signal averaged down by this much first, then only
+// the frames around that match are compared at full resolution
+const STRETCH_DECIMATION: usize = 8;
+
+
+// The music of a play. At 1.0x it is streamed like the menus do, any other rate
+// decodes the whole file up front and changes its speed.
+pub enum Track {
+    Stream(Music<'static>),
+    Buffer {
+        samples: Vec<i16>,
+        channels: usize,
+        frequency: usize,
+        // What is playing, a copy of the samples from where playback started
+        chunk: Option<Chunk>,
+    },
+    // The song has no music that loads. Nothing plays and the chart ends with its last note.
+    Silent,
+}
+
+
+impl Track {
+    pub fn load(path: &Path, modifiers: &Modifiers) -> Result<Track, String> {
+        if modifiers.rate == 1.0 {
+            return Music::from_file(path).map(Track::Stream);
+        }
+
+        let (frequency, _, channels) = sdl2::mixer::query_spec()?;
+        let channels = channels.max(1) as usize;
+        let samples = decode(path)?;
+        let frequency = frequency as usize;
+        let samples = if modifiers.preserve_pitch {
+            time_stretch(&samples, channels, modifiers.rate, frequency)
+        } else {
+            resample(&samples, channels, modifiers.rate)
+        };
+        Ok(Track::Buffer { samples, channels, frequency, chunk: None })
+    }
+
+    // Plays from `position` seconds into the track (already at the track's rate)
+    pub fn play_from(&mut self, position: f64) -> Result<(), String> {
+        match self {
+            Track::Stream(music) => {
+                music.play(0)?;
+                if position > 0.0 {
+                    Music::set_pos(position)?;
+                }
+                Ok(())
+            }
+            Track::Buffer { samples, channels, frequency, chunk } => {
+                let start = ((position * *frequency as f64) as usize * *channels).min(samples.len());
+                MUSIC_CHANNEL.halt();
+                let playing = chunk.insert(Chunk::from_raw_buffer(samples[start..].to_vec().into_boxed_slice())?);
+                MUSIC_CHANNEL.play(playing, 0).map(|_| ())
+            }
+            Track::Silent => Ok(()),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        match self {
+            Track::Stream(_) => Music::is_playing(),
+            Track::Buffer { .. } => MUSIC_CHANNEL.is_playing(),
+            Track::Silent => false,
+        }
+    }
+
+    pub fn pause() {
+        Music::pause();
+        MUSIC_CHANNEL.pause();
+    }
+
+    pub fn resume() {
+        Music::resume();
+        MUSIC_CHANNEL.resume();
+    }
+
+    pub fn halt() {
+        Music::halt();
+        MUSIC_CHANNEL.halt();
+    }
+}
+
+
+// Whole file as interleaved samples in the mixer's format (16 bit, mixer channels and frequency)
+fn decode(path: &Path) -> Result<Vec<i16>, String> {
+    let rwops = RWops::from_file(path, "rb")?;
+    unsafe {
+        let chunk = sdl2::sys::mixer::Mix_LoadWAV_RW(rwops.raw(), 0);
+        if chunk.is_null() {
+            return Err(sdl2::get_error());
+        }
+        let bytes = std::slice::from_raw_parts((*chunk).abuf, (*chunk).alen as usize);
+        let samples = bytes.chunks_exact(2).map(|b| i16::from_ne_bytes([b[0], b[1]])).collect();
+        sdl2::sys::mixer::Mix_FreeChunk(chunk);
+        Ok(samples)
+    }
+}
+
+
+// Plays the samples `rate` times faster by linear interpolation, the pitch moves with the rate
+fn resample(samples: &[i16], channels: usize, rate: f64) -> Vec<i16> {
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return Vec::new();
+    }
+    let out_frames = (frames as f64 / rate) as usize;
+
+    let mut out = Vec::with_capacity(out_frames * channels);
+    for i in 0..out_frames {
+        let position = i as f64 * rate;
+        let frame = (position as usize).min(frames - 1);
+        let next = (frame + 1).min(frames - 1);
+        let fraction = position - frame as f64;
+        for c in 0..channels {
+            let a = samples[frame * channels + c] as f64;
+            let b = samples[next * channels + c] as f64;
+            out.push((a + (b - a) * fraction) as i16);
+        }
+    }
+    out
+}
+
+
+// WSOLA: overlap-adds windows read `rate` times further apart than they are written,
+// each one moved a little to where it lines up best with the audio before it.
+// The pitch stays the same, at the cost of some smearing on sharp sounds.
+fn time_stretch(samples: &[i16], channels: usize, rate: f64, frequency: usize) -> Vec<i16> {
+    let frames = samples.len() / channels;
+    let window = (frequency * STRETCH_WINDOW_MS / 1000).max(64);
+    let hop = window / 2;
+    let tolerance = frequency * STRETCH_TOLERANCE_MS / 1000;
+
+    let mono: Vec<f32> = samples.chunks_exact(channels)
+        .map(|frame| frame.iter().map(|&s| s as f32).sum())
+        .collect();
+    let coarse: Vec<f32> = mono.chunks(STRETCH_DECIMATION)
+        .map(|frames| frames.iter().sum::<f32>() / frames.len() as f32)
+        .collect();
+    // Hann windows at half overlap add up to exactly one
+    let hann: Vec<f32> = (0..window).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / window as f32).cos()).collect();
+
+    let out_frames = (frames as f64 / rate) as usize;
+    let mut out = vec![0.0f32; (out_frames + window) * channels];
+    let mut previous = 0;
+
+    for block in 0..out_frames.div_ceil(hop) {
+        let target = (block as f64 * hop as f64 * rate) as usize;
+        let start = if block == 0 {
+            0
+        } else {
+            best_alignment(&mono, &coarse, previous + hop, target, tolerance, hop)
+        };
+
+        for (i, weight) in hann.iter().enumerate() {
+            let frame = start + i;
+            if frame >= frames {
+                break;
+            }
+            for c in 0..channels {
+                out[(block * hop + i) * channels + c] += samples[frame * channels + c] as f32 * weight;
+            }
+        }
+        previous = start;
+    }
+
+    out.truncate(out_frames * channels);
+    out.into_iter().map(|s| s.clamp(i16::MIN as f32, i16::MAX as f32) as i16).collect()
+}
+
+
+// Start frame near `target` whose first `length` frames correlate best with the ones at `natural`,
+// the frames that would have followed the previous window. `coarse` is `mono` decimated
+// by STRETCH_DECIMATION, the whole range is searched on it and the best match refined on `mono`.
+fn best_alignment(mono: &[f32], coarse: &[f32], natural: usize, target: usize, tolerance: usize, length: usize) -> usize {
+    let low = target.saturating_sub(tolerance);
+    let high = (target + tolerance).min(mono.len().saturating_sub(length));
+    if low >= high || natural + length > mono.len() {
+        return target;
+    }
+
+    let correlation = |signal: &[f32], candidate: usize, natural: usize, length: usize, step: usize| -> f32 {
+        (0..length).step_by(step).map(|i| signal[candidate + i] * signal[natural + i]).sum()
+    };
+    let best = |candidates: std::ops::RangeInclusive<usize>, score: &dyn Fn(usize) -> f32| {
+        let first = *candidates.start();
+        candidates.map(|candidate| (candidate, score(candidate)))
+            .fold((first, f32::MIN), |best, next| if next.1 > best.1 { next } else { best })
+            .0
+    };
+
+    let d = STRETCH_DECIMATION;
+    let rough = best(low / d..=high / d, &|candidate| correlation(coarse, candidate, natural / d, length / d, 1)) * d;
+    // Every fourth frame is plenty to find the peak near the rough match
+    best(rough.saturating_sub(d).max(low)..=(rough + d).min(high), &|candidate| correlation(mono, candidate, natural, length, 4))
+}
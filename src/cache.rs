@@ -0,0 +1,205 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::config;
+use crate::game_state::GameState;
+use crate::library::Song;
+
+// Bump when the cached fields change so old caches get rebuilt
+const CACHE_VERSION: &str = "6";
+
+
+struct CacheEntry {
+    mtime: u128,
+    hash: u64,
+    song: Song,
+}
+
+
+// Parsed simfile metadata keyed by .sm path. An entry is reused while the file's
+// mtime is unchanged, or when it changed but the contents hash to the same value.
+pub struct SongCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, CacheEntry>,
+    used: HashSet<PathBuf>,
+    parsed: usize,
+}
+
+
+impl SongCache {
+    pub fn load(rebuild: bool) -> Self {
+        let path = config::data_dir().join("song_cache.txt");
+        let mut cache = Self {
+            entries: HashMap::new(),
+            used: HashSet::new(),
+            parsed: 0,
+            path,
+        };
+
+        if rebuild {
+            return cache;
+        }
+        let Some(tags) = config::read_tags(&cache.path) else {
+            return cache;
+        };
+        if tags.first() != Some(&("VERSION".to_string(), CACHE_VERSION.to_string())) {
+            return cache;
+        }
+
+        // Every entry starts with its #SONG tag
+        let mut current: Option<(PathBuf, CacheEntry)> = None;
+        for (tag, value) in tags.into_iter().skip(1) {
+            if tag == "SONG" {
+                if let Some((sm_file, entry)) = current.take() {
+                    cache.entries.insert(sm_file, entry);
+                }
+                current = Some((PathBuf::from(&value), CacheEntry {
+                    mtime: 0,
+                    hash: 0,
+                    song: Song { selectable: true, ..Song::default() },
+                }));
+                continue;
+            }
+
+            if let Some((_, entry)) = &mut current {
+                Self::read_field(entry, &tag, value);
+            }
+        }
+        if let Some((sm_file, entry)) = current {
+            cache.entries.insert(sm_file, entry);
+        }
+
+        cache
+    }
+
+    // Simfile metadata from the cache, parsing the file only when it has changed
+    pub fn song(&mut self, sm_file: &Path) -> Option<Song> {
+        let mtime = Self::modified(sm_file);
+        self.used.insert(sm_file.to_path_buf());
+
+        if let Some(entry) = self.entries.get(sm_file)
+            && entry.mtime == mtime {
+            return Some(entry.song.clone());
+        }
+
+        let bytes = fs::read(sm_file).ok()?;
+        let hash = config::content_hash(&bytes);
+
+        if let Some(entry) = self.entries.get_mut(sm_file)
+            && entry.hash == hash {
+            entry.mtime = mtime;
+            return Some(entry.song.clone());
+        }
+
+        let contents = String::from_utf8_lossy(&bytes);
+        let game_state = GameState::from_sm(&contents, 0.0);
+        let song = Song::from_game_state(Path::new(""), sm_file, "", &game_state);
+        self.parsed += 1;
+
+        self.entries.insert(sm_file.to_path_buf(), CacheEntry { mtime, hash, song: song.clone() });
+        Some(song)
+    }
+
+    pub fn parsed(&self) -> usize {
+        self.parsed
+    }
+
+    // Writes the entries seen during this run, songs that were removed from disk drop out
+    pub fn save(&self) {
+        let mut tags = vec![("VERSION".to_string(), CACHE_VERSION.to_string())];
+
+        let mut paths: Vec<&PathBuf> = self.used.iter().filter(|path| self.entries.contains_key(*path)).collect();
+        paths.sort();
+
+        for sm_file in paths {
+            let entry = &self.entries[sm_file];
+            let song = &entry.song;
+            tags.extend([
+                ("SONG".to_string(), sm_file.to_string_lossy().to_string()),
+                ("MTIME".to_string(), entry.mtime.to_string()),
+                ("HASH".to_string(), format!("{:016x}", entry.hash)),
+                ("TITLE".to_string(), song.title.clone()),
+                ("SUBTITLE".to_string(), song.subtitle.clone()),
+                ("ARTIST".to_string(), song.artist.clone()),
+                ("TITLETRANSLIT".to_string(), song.title_translit.clone()),
+                ("SUBTITLETRANSLIT".to_string(), song.subtitle_translit.clone()),
+                ("ARTISTTRANSLIT".to_string(), song.artist_translit.clone()),
+                ("GENRE".to_string(), song.genre.clone()),
+                ("CREDIT".to_string(), song.credit.clone()),
+                ("MUSIC".to_string(), song.music.clone()),
+                ("BANNER".to_string(), song.banner.clone()),
+                ("CDTITLE".to_string(), song.cdtitle.clone()),
+                ("DISPLAYBPM".to_string(), song.display_bpm.clone()),
+                ("SELECTABLE".to_string(), if song.selectable { "YES" } else { "NO" }.to_string()),
+                ("BPMS".to_string(), format!("{}={}", song.min_bpm, song.max_bpm)),
+                ("LENGTH".to_string(), song.length.to_string()),
+                ("SAMPLE".to_string(), format!("{}={}", song.sample_start, song.sample_len)),
+            ]);
+            // One tag per chart, difficulty names may have commas in them
+            tags.extend(song.difficulties.iter().enumerate().map(|(i, diff)| {
+                ("CHART".to_string(), format!("{}={:016x}={}={}={}",
+                    song.steps_types.get(i).map(String::as_str).unwrap_or_default(),
+                    song.chart_hashes.get(i).copied().unwrap_or(0),
+                    diff,
+                    song.meters.get(i).copied().unwrap_or(0),
+                    song.note_counts.get(i).copied().unwrap_or(0)))
+            }));
+        }
+
+        config::write_tags(&self.path, &tags);
+    }
+
+    fn read_field(entry: &mut CacheEntry, tag: &str, value: String) {
+        let song = &mut entry.song;
+        let pair = |value: &str| -> (f64, f64) {
+            let (a, b) = value.split_once('=').unwrap_or_default();
+            (a.parse().unwrap_or(0.0), b.parse().unwrap_or(0.0))
+        };
+
+        match tag {
+            "MTIME" => entry.mtime = value.parse().unwrap_or(0),
+            "HASH" => entry.hash = u64::from_str_radix(&value, 16).unwrap_or(0),
+            "TITLE" => song.title = value,
+            "SUBTITLE" => song.subtitle = value,
+            "ARTIST" => song.artist = value,
+            "TITLETRANSLIT" => song.title_translit = value,
+            "SUBTITLETRANSLIT" => song.subtitle_translit = value,
+            "ARTISTTRANSLIT" => song.artist_translit = value,
+            "GENRE" => song.genre = value,
+            "CREDIT" => song.credit = value,
+            "MUSIC" => song.music = value,
+            "BANNER" => song.banner = value,
+            "CDTITLE" => song.cdtitle = value,
+            "DISPLAYBPM" => song.display_bpm = value,
+            "SELECTABLE" => song.selectable = value != "NO",
+            "CHART" => {
+                // The difficulty name is the only field that could contain a '='
+                let (steps_type, chart) = value.split_once('=').unwrap_or_default();
+                let (hash, chart) = chart.split_once('=').unwrap_or_default();
+                let mut parts = chart.rsplitn(3, '=');
+                let notes = parts.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+                let meter = parts.next().and_then(|m| m.parse().ok()).unwrap_or(0);
+                song.steps_types.push(steps_type.to_string());
+                song.chart_hashes.push(u64::from_str_radix(hash, 16).unwrap_or(0));
+                song.difficulties.push(parts.next().unwrap_or_default().to_string());
+                song.meters.push(meter);
+                song.note_counts.push(notes);
+            }
+            "BPMS" => (song.min_bpm, song.max_bpm) = pair(&value),
+            "LENGTH" => song.length = value.parse().unwrap_or(0.0),
+            "SAMPLE" => (song.sample_start, song.sample_len) = pair(&value),
+            _ => {}
+        }
+    }
+
+    fn modified(path: &Path) -> u128 {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+
+// Chart time in seconds. Unlike a bare Instant it can be paused, and it can read negative
+// during the lead-in before the music starts.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    origin: Instant,
+    base: f64, // chart time at `origin`
+    running: bool,
+}
+
+
+impl Clock {
+    // A running clock that reads `chart_time` right now
+    pub fn starting_at(chart_time: f64) -> Self {
+        Self { origin: Instant::now(), base: chart_time, running: true }
+    }
+
+    pub fn time(&self) -> f64 {
+        if self.running {
+            self.base + self.origin.elapsed().as_secs_f64()
+        } else {
+            self.base
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        !self.running
+    }
+
+    pub fn pause(&mut self) {
+        if self.running {
+            self.base = self.time();
+            self.running = false;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if !self.running {
+            self.origin = Instant::now();
+            self.running = true;
+        }
+    }
+}
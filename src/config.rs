@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Everything the game writes on its own (bindings, caches, scores...) lives here
+const DATA_DIR: &str = "./data";
+
+
+pub fn data_dir() -> PathBuf {
+    let dir = PathBuf::from(DATA_DIR);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Failed to create data directory {}: {}", dir.display(), e);
+    }
+    dir
+}
+
+
+// Reads every #TAG:value; pair in file order, same syntax as the .sm files.
+// Unlike GameState::parse_tag_value a tag may appear more than once.
+pub fn parse_tags(contents: &str) -> Vec<(String, String)> {
+    contents.split(';')
+        .filter_map(|entry| {
+            let start = entry.find('#')?;
+            let (tag, value) = entry[start + 1..].split_once(':')?;
+            Some((tag.trim().to_uppercase(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+
+pub fn read_tags(path: &Path) -> Option<Vec<(String, String)>> {
+    fs::read_to_string(path).ok().map(|contents| parse_tags(&contents))
+}
+
+
+pub fn write_tags(path: &Path, tags: &[(String, String)]) {
+    let mut contents = String::new();
+    for (tag, value) in tags {
+        contents += &format!("#{}:{};\n", tag, value);
+    }
+
+    if let Err(e) = fs::write(path, contents) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+
+// FNV-1a. Unlike std's hasher it stays the same between runs and builds,
+// so it can be written to disk.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
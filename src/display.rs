@@ -0,0 +1,40 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::Canvas;
+use sdl2::video::{FullscreenType, Window};
+
+// Every screen is laid out on this virtual screen. SDL scales it to whatever size the
+// window is, keeping its shape with bars on the sides or at the top and bottom.
+pub const WIDTH: u32 = 800;
+pub const HEIGHT: u32 = 600;
+
+
+pub fn setup(canvas: &mut Canvas<Window>, fullscreen: bool) {
+    // Smooth scaling, for textures created from here on
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "1");
+    canvas.set_logical_size(WIDTH, HEIGHT).unwrap();
+    canvas.window_mut().set_minimum_size(WIDTH / 2, HEIGHT / 2).unwrap();
+    if fullscreen {
+        toggle_fullscreen(canvas);
+    }
+}
+
+
+// F11 switches between a window and borderless fullscreen, on any screen
+pub fn handle_event(canvas: &mut Canvas<Window>, event: &Event) {
+    if let Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } = event {
+        toggle_fullscreen(canvas);
+    }
+}
+
+
+fn toggle_fullscreen(canvas: &mut Canvas<Window>) {
+    let window = canvas.window_mut();
+    let state = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    if let Err(e) = window.set_fullscreen(state) {
+        eprintln!("Failed to change fullscreen: {}", e);
+    }
+}
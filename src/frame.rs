@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::sys::SDL_RendererFlags;
+use sdl2::ttf::Font;
+use sdl2::video::Window;
+
+use crate::display;
+use crate::text::TextCache;
+
+// Frame rate the loop is held to when present() doesn't wait for vsync
+const FRAME_RATE: f64 = 120.0;
+// Frames the overlay averages over
+const SAMPLES: usize = 60;
+// Sleeps can overshoot, the last stretch before a deadline is spun instead
+const SPIN: Duration = Duration::from_micros(1500);
+// How often the overlay's numbers change, so they can be read
+const OVERLAY_REFRESH: Duration = Duration::from_millis(500);
+
+// Ends each frame of a screen's loop. With vsync present() already waits for the display,
+// otherwise frames are held to FRAME_RATE against a deadline so they don't drift.
+// One is made at startup and handed to every screen, so the overlay stays on between them.
+pub struct FramePacer {
+    vsync: bool,
+    next_frame: Instant,
+    last_frame: Instant,
+    frame_times: VecDeque<f64>, // seconds
+    overlay: bool, // F3
+    overlay_text: String,
+    overlay_updated: Instant,
+}
+
+
+impl FramePacer {
+    pub fn new(canvas: &Canvas<Window>) -> Self {
+        let vsync = canvas.info().flags & SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0;
+        let now = Instant::now();
+        Self {
+            vsync,
+            next_frame: now,
+            last_frame: now,
+            frame_times: VecDeque::with_capacity(SAMPLES),
+            overlay: false,
+            overlay_text: String::new(),
+            overlay_updated: now,
+        }
+    }
+
+    // F3 shows or hides the overlay, on any screen
+    pub fn handle_event(&mut self, event: &Event) {
+        if let Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } = event {
+            self.overlay = !self.overlay;
+        }
+    }
+
+    // Call right after present()
+    pub fn wait(&mut self) {
+        if !self.vsync {
+            self.next_frame += Duration::from_secs_f64(1.0 / FRAME_RATE);
+            let now = Instant::now();
+            if self.next_frame <= now {
+                // Running behind, start counting again from here rather than rushing to catch up
+                self.next_frame = now;
+            } else {
+                if let Some(sleep) = (self.next_frame - now).checked_sub(SPIN) {
+                    std::thread::sleep(sleep);
+                }
+                while Instant::now() < self.next_frame {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if self.frame_times.len() == SAMPLES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back((now - self.last_frame).as_secs_f64());
+        self.last_frame = now;
+
+        if now - self.overlay_updated >= OVERLAY_REFRESH {
+            let average = self.frame_times.iter().sum::<f64>() / self.frame_times.len() as f64;
+            let worst = self.frame_times.iter().copied().fold(0.0, f64::max);
+            let vsync = if self.vsync { "  vsync" } else { "" };
+            self.overlay_text = format!("{:.0} FPS  {:.1} / {:.1} ms{}", 1.0 / average, average * 1000.0, worst * 1000.0, vsync);
+            self.overlay_updated = now;
+        }
+    }
+
+    // FPS and the average and worst frame time (in ms) in the top right corner, when it is turned on
+    pub fn draw_overlay(&self, canvas: &mut Canvas<Window>, text_cache: &mut TextCache, font: &Font) {
+        if !self.overlay || self.overlay_text.is_empty() {
+            return;
+        }
+        let texture = text_cache.texture(font, &self.overlay_text, Color::RGB(120, 255, 120));
+        let query = texture.query();
+        let rect = Rect::new(display::WIDTH as i32 - 5 - query.width as i32, 3, query.width, query.height);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        canvas.fill_rect(rect).unwrap();
+        canvas.copy(texture, None, rect).unwrap();
+    }
+}
@@ -1,7 +1,17 @@
-use std::collections::HashMap;
 use std::fs;
-use std::time::{Instant, Duration};
-use ordered_float::OrderedFloat;
+
+use crate::clock::Clock;
+use crate::config;
+use crate::input::LANES;
+use crate::modifiers::Modifiers;
+use crate::timing::{self, TimingData};
+
+// Practice sections count in from this many seconds before the music comes in
+const COUNTDOWN: f64 = 3.0;
+// and the music comes in this long before the section's first note
+const SECTION_PREROLL: f64 = 1.0;
+// The only steps type laid out on the four lanes, doubles and pump charts have more columns
+const FOUR_PANEL_STEPS_TYPE: &str = "dance-single";
 
 #[derive(Debug, Clone)]
 pub struct Arrow {
@@ -10,96 +20,202 @@ pub struct Arrow {
     pub spawn_time: f64,
     pub long: bool,
     pub end_time: f64,
+    pub mine: bool,
+    pub roll: bool, // a long note that has to be tapped rather than held
+    pub row: u32, // position on the chart grid, timing::ROWS_PER_BEAT to a beat
+    pub quantization: u32, // note value of the row: 4 for 4ths, 8 for 8ths, 12 for 12ths...
 }
 
 pub struct GameState {
     pub difficulties: Vec<String>,
-    pub start_time: Instant,
+    pub steps_types: Vec<String>,
+    pub clock: Clock,
     pub arrows: Vec<Arrow>,
+    // Chart time the music starts playing at, and from that position in the music
+    pub music_start: f64,
+    // Chart time a practice section is over, plays without one end with the music
+    pub section_end: Option<f64>,
     
     pub song: String,
     pub artist: String,
     pub title: String,
+    pub subtitle: String,
+    pub title_translit: String,
+    pub subtitle_translit: String,
+    pub artist_translit: String,
+    pub genre: String,
+    pub credit: String,
     pub bg: String,
+    pub banner: String,
+    pub cdtitle: String,
+    // #DISPLAYBPM as written: a BPM, min:max, or * for one that keeps changing
+    pub display_bpm: String,
+    // #SELECTABLE:NO; keeps a song off the wheel
+    pub selectable: bool,
 
     pub sample_start: f64,
     pub sample_len: f64,
 
+    pub meters: Vec<u32>,
+    // Hash of each chart's note rows, scores are stored under it so renaming a folder doesn't lose them
+    pub chart_hashes: Vec<u64>,
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    // (beat, name) of the simfile's #LABELS
+    pub labels: Vec<(f64, String)>,
+    // Measures in each chart
+    pub measure_counts: Vec<u32>,
+
+    speed: f64,
+    offset: f64,
+    timing: TimingData,
     all_arrows: Vec<Vec<Arrow>>
 }
 
 
 
 impl GameState {
-    pub fn new(file_path: &str, speed: f64) -> Self {
-        let contents = fs::read_to_string(file_path).expect("Failed to read file");
-        let (mut all_arrows,
-             difficulties,
-             song, 
-             artist, 
-             title, 
-             bg, 
-             sample_start,
-             sample_len) = Self::parse_sm_file(&contents);
-
-        for arrows in &mut all_arrows {
+    pub fn new(file_path: &str, speed: f64, offset: f64) -> Self {
+        // Plenty of simfiles in the wild aren't UTF-8, don't let one odd title stop the parse
+        let contents = String::from_utf8_lossy(&fs::read(file_path).expect("Failed to read file")).to_string();
+        let mut game_state = Self::from_sm(&contents, speed);
+        game_state.offset = offset;
+        game_state
+    }
+
+    pub fn from_sm(contents: &str, speed: f64) -> Self {
+        let mut game_state = Self::parse_sm_file(contents);
+        game_state.speed = speed;
+
+        for arrows in &mut game_state.all_arrows {
             for arrow in arrows {
                 arrow.spawn_time = arrow.hit_time - speed;
             }
         }
 
-        let arrows: Vec<Arrow> = Vec::new();
+        game_state
+    }
 
-        Self {
-            difficulties,
-            start_time: Instant::now(),
-            arrows,
-            song,
-            artist,
-            title,
-            bg,
-            sample_start,
-            sample_len,
-            all_arrows
-        }
+    pub fn playable(&self, difficulty: u32) -> bool {
+        self.steps_types.get(difficulty as usize).is_some_and(|steps_type| steps_type == FOUR_PANEL_STEPS_TYPE)
+    }
+
+    pub fn note_counts(&self) -> Vec<usize> {
+        self.all_arrows.iter().map(|arrows| arrows.iter().filter(|arrow| !arrow.mine).count()).collect()
+    }
+
+    // Time of the last note over all difficulties
+    pub fn length(&self) -> f64 {
+        self.all_arrows.iter()
+            .flatten()
+            .map(|arrow| arrow.hit_time.max(arrow.end_time))
+            .fold(0.0, f64::max)
     }
 
-    pub fn start(&mut self, difficulty: u32){
-        let arrows: Vec<Arrow> = self.all_arrows
+    // A copy of the chart with the modifiers applied.
+    // Music at `rate` times the speed puts every note at 1/rate of its time.
+    // The player's audio offset is added after, it doesn't scale with the music.
+    fn timed_arrows(&self, difficulty: u32, modifiers: &Modifiers) -> Vec<Arrow> {
+        let rate = modifiers.rate;
+        let mut arrows = self.all_arrows
             .get(difficulty as usize)   
             .cloned()
             .unwrap_or_default();
+        modifiers.apply_transforms(&mut arrows, |row| self.timing.row_time(row));
+
+        let mut arrows: Vec<Arrow> = arrows
+            .into_iter()
+            .map(|arrow| {
+                let hit_time = arrow.hit_time / rate + self.offset;
+                Arrow {
+                    hit_time,
+                    spawn_time: hit_time - self.speed,
+                    end_time: arrow.end_time / rate + self.offset,
+                    ..arrow
+                }
+            })
+            .collect();
 
-        let mut start_time = 1.0;
-        if let Some(arrow) = arrows.first(){
-            if arrow.spawn_time < 0.0 {
-                start_time -= arrow.spawn_time;
-            }
+        modifiers.apply_turn(&mut arrows);
+        arrows
+    }
+
+    pub fn start(&mut self, difficulty: u32, modifiers: &Modifiers){
+        let arrows = self.timed_arrows(difficulty, modifiers);
 
+        let mut start_time = 1.0;
+        if let Some(arrow) = arrows.first()
+            && arrow.spawn_time < 0.0 {
+            start_time -= arrow.spawn_time;
         }
 
 
-        self.start_time = Instant::now() + Duration::from_secs_f64(start_time);
+        self.clock = Clock::starting_at(-start_time);
         self.arrows = arrows;
+        self.music_start = 0.0;
+        self.section_end = None;
+    }
+
+    // Plays the chart from the start of measure `from` up to the start of measure `to`
+    pub fn start_section(&mut self, difficulty: u32, modifiers: &Modifiers, from: u32, to: u32) {
+        let rate = modifiers.rate;
+        let from_time = self.beat_time(from as f64 * 4.0) / rate + self.offset;
+        let to_time = self.beat_time(to as f64 * 4.0) / rate + self.offset;
+
+        let arrows: Vec<Arrow> = self.timed_arrows(difficulty, modifiers)
+            .into_iter()
+            .filter(|arrow| arrow.hit_time >= from_time && arrow.hit_time < to_time)
+            .collect();
+        let first_spawn = arrows.iter().map(|arrow| arrow.spawn_time).fold(from_time, f64::min);
+        let last_end = arrows.iter().map(|arrow| arrow.hit_time.max(arrow.end_time)).fold(to_time, f64::max);
+
+        // The music can't start before its beginning, the countdown also leaves the first notes time to scroll in
+        self.music_start = (from_time - SECTION_PREROLL).max(0.0);
+        self.section_end = Some(last_end + SECTION_PREROLL);
+        self.clock = Clock::starting_at((self.music_start - COUNTDOWN).min(first_spawn));
+        self.arrows = arrows;
+    }
 
+    // Seconds into the music at 1.0x, without the player's offset
+    pub fn beat_time(&self, beat: f64) -> f64 {
+        self.timing.beat_time(beat)
     }
 
 
-    fn parse_sm_file(content: &str) -> (Vec<Vec<Arrow>>, Vec<String>, String, String, String, String, f64, f64) {
-        let mut bpm_map: HashMap<OrderedFloat<f64>, f64> = HashMap::new();
-        let mut stop_map: HashMap<OrderedFloat<f64>, f64> = HashMap::new();
+    fn parse_sm_file(content: &str) -> Self {
+        let mut bpms: Vec<(f64, f64)> = Vec::new();
+        let mut stops: Vec<(f64, f64)> = Vec::new();
         let mut measures: Vec<Vec<Vec<&str>>> = Vec::new();
         let mut current_measure: Vec<&str> = Vec::new();
         let mut all_arrows: Vec<Vec<Arrow>> = Vec::new();
         let mut difficulties: Vec<String> = Vec::new();
+        let mut steps_types: Vec<String> = Vec::new();
+        let mut meters: Vec<u32> = Vec::new();
 
         let title = Self::parse_tag_value(content, "#TITLE:").unwrap_or_default();
         let artist = Self::parse_tag_value(content, "#ARTIST:").unwrap_or_default();
+        let subtitle = Self::parse_tag_value(content, "#SUBTITLE:").unwrap_or_default();
+        let title_translit = Self::parse_tag_value(content, "#TITLETRANSLIT:").unwrap_or_default();
+        let subtitle_translit = Self::parse_tag_value(content, "#SUBTITLETRANSLIT:").unwrap_or_default();
+        let artist_translit = Self::parse_tag_value(content, "#ARTISTTRANSLIT:").unwrap_or_default();
         let song = Self::parse_tag_value(content, "#MUSIC:").unwrap_or_default();
         let bg = Self::parse_tag_value(content, "#BACKGROUND:").unwrap_or_default();
+        let genre = Self::parse_tag_value(content, "#GENRE:").unwrap_or_default();
+        let credit = Self::parse_tag_value(content, "#CREDIT:").unwrap_or_default();
+        let banner = Self::parse_tag_value(content, "#BANNER:").unwrap_or_default();
+        let cdtitle = Self::parse_tag_value(content, "#CDTITLE:").unwrap_or_default();
+        let display_bpm = Self::parse_tag_value(content, "#DISPLAYBPM:").unwrap_or_default();
+        let selectable = Self::parse_tag_value(content, "#SELECTABLE:").is_none_or(|value| !value.eq_ignore_ascii_case("NO"));
         let offset: f64 = Self::parse_tag_value(content, "#OFFSET:").and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0).abs();
         let sample_start = Self::parse_tag_value(content, "#SAMPLESTART:").and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0);
-        let sample_len = Self::parse_tag_value(content, "#SAMPLELENGTH:").and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0);
+        let sample_len = Self::parse_tag_value(content, "#SAMPLELENGTH:").and_then(|s| s.parse::<f64>().ok()).filter(|&len| len > 0.0).unwrap_or(12.0);
+        let labels = Self::parse_tag_value(content, "#LABELS:").unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (beat, name) = entry.split_once('=')?;
+                Some((beat.trim().parse::<f64>().ok()?, name.trim().to_string()))
+            })
+            .collect();
 
 
 
@@ -112,7 +228,7 @@ impl GameState {
                 }
 
                 if let (Ok(beat), Ok(bpm)) = (parts[0].parse::<f64>(), parts[1].parse::<f64>()) {
-                    bpm_map.insert(OrderedFloat(beat), bpm);
+                    bpms.push((beat, bpm));
                 }
             }            
         }
@@ -124,7 +240,7 @@ impl GameState {
                 }
 
                 if let (Ok(beat), Ok(bpm)) = (parts[0].parse::<f64>(), parts[1].trim().parse::<f64>()) {
-                    stop_map.insert(OrderedFloat(beat), bpm);
+                    stops.push((beat, bpm));
 
                 }
                 
@@ -139,13 +255,22 @@ impl GameState {
         for line in content.lines() {
             let line = line.trim();
             if line.starts_with("#NOTES") {
-                lines_since_notes = 0;
-                parsing = true;
-
-                if !current_measure_difficulty.is_empty(){
+                // Every block gets an entry in each list, even one without notes,
+                // so a chart's hash, meter and arrows stay at its difficulty's index
+                if parsing {
+                    if !current_measure.is_empty() {
+                        current_measure_difficulty.push(current_measure);
+                        current_measure = Vec::new();
+                    }
                     measures.push(current_measure_difficulty);
                     current_measure_difficulty = Vec::new();
                 }
+                steps_types.push(String::new());
+                difficulties.push(String::new());
+                meters.push(0);
+
+                lines_since_notes = 0;
+                parsing = true;
 
             } else {
                 if !parsing {
@@ -153,8 +278,17 @@ impl GameState {
                 }
 
                 lines_since_notes += 1;
-                if lines_since_notes == 3 {
-                    difficulties.push(line.trim_end_matches(':').to_string());
+                if lines_since_notes == 1
+                    && let Some(steps_type) = steps_types.last_mut() {
+                    *steps_type = line.trim_end_matches(':').to_string();
+                }
+                if lines_since_notes == 3
+                    && let Some(difficulty) = difficulties.last_mut() {
+                    *difficulty = line.trim_end_matches(':').to_string();
+                }
+                if lines_since_notes == 4
+                    && let Some(meter) = meters.last_mut() {
+                    *meter = line.trim_end_matches(':').trim().parse().unwrap_or(0);
                 }
 
                 if line.starts_with(",") {
@@ -162,10 +296,8 @@ impl GameState {
                         current_measure_difficulty.push(current_measure);
                         current_measure = Vec::new();
                     }
-                } else if !line.is_empty() {
-                    if line.chars().all(|c| c == '0' || c == '1' || c == '2' || c=='3') {
-                        current_measure.push(line);
-                    }
+                } else if !line.is_empty() && line.chars().all(|c| "01234MKLF".contains(c)) {
+                    current_measure.push(line);
                 }
             }
         }
@@ -174,14 +306,26 @@ impl GameState {
         if !current_measure.is_empty() {
             current_measure_difficulty.push(current_measure);
         }
-        if !current_measure_difficulty.is_empty(){
+        if parsing {
             measures.push(current_measure_difficulty);
         }
 
 
+        let chart_hashes = measures.iter()
+            .map(|difficulty| {
+                let rows: Vec<String> = difficulty.iter().map(|measure| measure.join("\n")).collect();
+                config::content_hash(rows.join(",").as_bytes())
+            })
+            .collect();
+
+        let measure_counts = measures.iter().map(|difficulty| difficulty.len() as u32).collect();
+
+        let timing = TimingData::new(offset, bpms, stops);
+
         for difficulty in measures{
             let mut measure_index = 0;
-            let mut last_long_start = [0.0,0.0,0.0,0.0];
+            let mut last_long_row = [0; LANES];
+            let mut last_long_roll = [false; LANES];
             let mut cur_arrows: Vec<Arrow> = Vec::new();
 
             for measure in difficulty {
@@ -189,32 +333,51 @@ impl GameState {
                 if num_lines == 0 {
                     continue;
                 }
-                let beat_increment = 4.0 / (num_lines as f64);
                 for (line_index, line) in measure.iter().enumerate() {
 
-                    let current_beat = (measure_index as f64) * 4.0 + (line_index as f64) * beat_increment;
+                    let row = timing::measure_row(measure_index, line_index, num_lines);
 
-                    for (lane, ch) in line.chars().enumerate() {
+                    // Columns past the fourth belong to charts that can't be played, see playable()
+                    for (lane, ch) in line.chars().enumerate().take(LANES) {
                         if ch == '1' {
-                            let hit_time = Self::beat_to_time(current_beat, &bpm_map, offset, &stop_map);
                             cur_arrows.push(Arrow {
                                 lane,
-                                hit_time,
+                                hit_time: timing.row_time(row),
+                                spawn_time: 0.0,
+                                long: false,
+                                end_time: 0.0,
+                                mine: false,
+                                roll: false,
+                                row,
+                                quantization: timing::quantization(row),
+                            });
+                        } else if ch == 'M' {
+                            cur_arrows.push(Arrow {
+                                lane,
+                                hit_time: timing.row_time(row),
                                 spawn_time: 0.0,
                                 long: false,
                                 end_time: 0.0,
+                                mine: true,
+                                roll: false,
+                                row,
+                                quantization: timing::quantization(row),
                             });
-                        } else if ch == '2' {
-                            last_long_start[lane] = Self::beat_to_time(current_beat, &bpm_map, offset, &stop_map);
+                        } else if ch == '2' || ch == '4' {
+                            last_long_row[lane] = row;
+                            last_long_roll[lane] = ch == '4';
                         } else if ch == '3' {
-                            let end_time = Self::beat_to_time(current_beat, &bpm_map, offset, &stop_map);
                             cur_arrows.push(Arrow {
                                 lane,
-                                hit_time: last_long_start[lane],
+                                hit_time: timing.row_time(last_long_row[lane]),
                                 spawn_time: 0.0,
                                 long: true,
-                                end_time,
-                            });                        
+                                end_time: timing.row_time(row),
+                                mine: false,
+                                roll: last_long_roll[lane],
+                                row: last_long_row[lane],
+                                quantization: timing::quantization(last_long_row[lane]),
+                            });
                         }
                     }
                 }
@@ -224,39 +387,45 @@ impl GameState {
         }
 
 
-        (all_arrows, difficulties, song, artist, title, bg, sample_start, sample_len)
-    }
-
-    fn beat_to_time(beat: f64, bpm_map: &HashMap<OrderedFloat<f64>, f64>, offset: f64, stop_map: &HashMap<OrderedFloat<f64>, f64>) -> f64 {
-        let mut last_time = offset;
-        let mut last_beat = 0.0;
-        let mut last_bpm = 120.0;
-
-        let mut beats: Vec<f64> = bpm_map.keys().map(|k| k.0).collect();
-        beats.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-        for &bpm_beat in &beats {
-            let bpm = bpm_map.get(&OrderedFloat(bpm_beat)).unwrap();
-            if beat < bpm_beat {
-                break;
-            }
-            last_time += (bpm_beat - last_beat) * (60.0 / last_bpm);
-            last_beat = bpm_beat;
-            last_bpm = *bpm;
-        }
-
+        let min_bpm = timing.min_bpm().unwrap_or(120.0);
+        let max_bpm = timing.max_bpm().unwrap_or(120.0);
 
-
-        for (&stop_beat, &stop_duration) in stop_map.iter(){
-            if f64::from(stop_beat) <= beat{
-                last_time += stop_duration;
-            }
+        Self {
+            difficulties,
+            steps_types,
+            clock: Clock::starting_at(0.0),
+            arrows: Vec::new(),
+            music_start: 0.0,
+            section_end: None,
+            song,
+            artist,
+            title,
+            subtitle,
+            title_translit,
+            subtitle_translit,
+            artist_translit,
+            genre,
+            credit,
+            bg,
+            banner,
+            cdtitle,
+            display_bpm,
+            selectable,
+            sample_start,
+            sample_len,
+            meters,
+            chart_hashes,
+            min_bpm,
+            max_bpm,
+            labels,
+            measure_counts,
+            speed: 0.0,
+            offset: 0.0,
+            timing,
+            all_arrows
         }
-
-        last_time + (beat - last_beat) * (60.0 / last_bpm)
     }
 
-
     fn parse_tag_value(contents: &str, tag: &str) -> Option<String> {
         if let Some(start) = contents.find(tag) {
             let rest = &contents[start + tag.len()..];
@@ -270,3 +439,22 @@ impl GameState {
 
 
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_charts_are_parsed_but_not_playable() {
+        let chart = "#TITLE:Test;\n#BPMS:0=120;\n\
+            #NOTES:\n     dance-double:\n     :\n     Hard:\n     9:\n     0,0,0,0,0:\n00000020\n00000000\n00000030\n10000001\n;\n\
+            #NOTES:\n     dance-single:\n     :\n     Easy:\n     2:\n     0,0,0,0,0:\n2000\n0000\n3000\n0001\n;\n";
+        let game_state = GameState::from_sm(chart, 1.0);
+
+        assert_eq!(game_state.difficulties, ["Hard", "Easy"]);
+        assert!(!game_state.playable(0));
+        assert!(game_state.playable(1));
+        assert_eq!(game_state.all_arrows[1].len(), 2);
+    }
+}
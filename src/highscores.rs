@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::modifiers::Modifiers;
+use crate::score::PlayResult;
+
+
+#[derive(Debug, Clone)]
+pub struct PlayRecord {
+    pub time: u64, // unix seconds
+    pub modifiers: String,
+    pub result: PlayResult,
+}
+
+
+impl PlayRecord {
+    // YYYY-MM-DD in UTC
+    pub fn date(&self) -> String {
+        // Days to civil date, from Howard Hinnant's date algorithms
+        let days = (self.time / 86400) as i64 + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    fn to_tag(&self) -> String {
+        let r = &self.result;
+        let judgments = r.judgments.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" ");
+        format!("{}={}={}={}={}={}={}={}",
+            self.time, r.score, judgments, r.max_combo, r.holds_ok, r.holds_ng, r.mines_hit, self.modifiers)
+    }
+
+    fn from_tag(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(8, '=');
+        let time = parts.next()?.parse().ok()?;
+
+        let mut result = PlayResult { score: parts.next()?.parse().ok()?, ..PlayResult::default() };
+        for (count, value) in result.judgments.iter_mut().zip(parts.next()?.split_whitespace()) {
+            *count = value.parse().ok()?;
+        }
+        result.max_combo = parts.next()?.parse().ok()?;
+        result.holds_ok = parts.next()?.parse().ok()?;
+        result.holds_ng = parts.next()?.parse().ok()?;
+        result.mines_hit = parts.next()?.parse().ok()?;
+
+        Some(Self { time, modifiers: parts.next().unwrap_or_default().to_string(), result })
+    }
+}
+
+
+// Every play of every chart, keyed by the chart's note data hash
+pub struct ScoreDb {
+    path: PathBuf,
+    charts: HashMap<u64, Vec<PlayRecord>>,
+}
+
+
+impl ScoreDb {
+    pub fn load(path: &Path) -> Self {
+        let path = path.to_path_buf();
+        let mut charts: HashMap<u64, Vec<PlayRecord>> = HashMap::new();
+
+        let mut current = None;
+        for (tag, value) in config::read_tags(&path).unwrap_or_default() {
+            match tag.as_str() {
+                "CHART" => current = u64::from_str_radix(&value, 16).ok(),
+                "PLAY" => {
+                    if let (Some(chart), Some(record)) = (current, PlayRecord::from_tag(&value)) {
+                        charts.entry(chart).or_default().push(record);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self { path, charts }
+    }
+
+    pub fn record(&mut self, chart: u64, result: &PlayResult, modifiers: &str) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.charts.entry(chart).or_default().push(PlayRecord {
+            time,
+            modifiers: modifiers.to_string(),
+            result: result.clone(),
+        });
+        self.save();
+    }
+
+    // Best play of the chart with modifiers that score alike, each rate and set of
+    // note transforms has its own scores
+    pub fn best(&self, chart: u64, modifiers: &Modifiers) -> Option<&PlayRecord> {
+        self.charts.get(&chart)?
+            .iter()
+            .filter(|record| Modifiers::from_tag(&record.modifiers).same_scoring(modifiers))
+            .max_by(|a, b| a.result.percentage().total_cmp(&b.result.percentage())
+                .then(a.result.score.cmp(&b.result.score)))
+    }
+
+    pub fn plays(&self, chart: u64) -> usize {
+        self.charts.get(&chart).map_or(0, |plays| plays.len())
+    }
+
+    pub fn play_count(&self, charts: &[u64]) -> usize {
+        charts.iter().map(|&chart| self.plays(chart)).sum()
+    }
+
+    fn save(&self) {
+        let mut hashes: Vec<&u64> = self.charts.keys().collect();
+        hashes.sort();
+
+        let mut tags = Vec::new();
+        for hash in hashes {
+            tags.push(("CHART".to_string(), format!("{:016x}", hash)));
+            for record in &self.charts[hash] {
+                tags.push(("PLAY".to_string(), record.to_tag()));
+            }
+        }
+        config::write_tags(&self.path, &tags);
+    }
+}
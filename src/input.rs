@@ -0,0 +1,455 @@
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::joystick::{HatState, Joystick};
+use sdl2::keyboard::Keycode;
+use sdl2::{GameControllerSubsystem, JoystickSubsystem, TimerSubsystem};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config;
+
+pub const LANES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Lane(usize),
+    Start,
+    Back,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Pressed(Action),
+    Released(Action),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HatDir {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// A physical control on a game controller or a raw joystick (most dance pads)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    PadButton(Button),
+    JoyButton(u8),
+    JoyHat(u8, HatDir),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Key(Keycode),
+    Device(u32, Binding),
+}
+
+
+pub struct Bindings {
+    keys: Vec<(Keycode, Action)>,
+    pad: Vec<(Binding, Action)>,
+    joystick: Vec<(Binding, Action)>,
+    // Per device overrides keyed by the SDL joystick GUID
+    devices: HashMap<String, Vec<(Binding, Action)>>,
+}
+
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let lanes = Action::Lane;
+        Self {
+            keys: vec![
+                (Keycode::D, lanes(0)),
+                (Keycode::F, lanes(1)),
+                (Keycode::J, lanes(2)),
+                (Keycode::K, lanes(3)),
+                (Keycode::Left, lanes(0)),
+                (Keycode::Down, lanes(1)),
+                (Keycode::Up, lanes(2)),
+                (Keycode::Right, lanes(3)),
+                (Keycode::Return, Action::Start),
+                (Keycode::Escape, Action::Back),
+            ],
+            pad: vec![
+                (Binding::PadButton(Button::DPadLeft), lanes(0)),
+                (Binding::PadButton(Button::DPadDown), lanes(1)),
+                (Binding::PadButton(Button::DPadUp), lanes(2)),
+                (Binding::PadButton(Button::DPadRight), lanes(3)),
+                (Binding::PadButton(Button::X), lanes(0)),
+                (Binding::PadButton(Button::A), lanes(1)),
+                (Binding::PadButton(Button::Y), lanes(2)),
+                (Binding::PadButton(Button::B), lanes(3)),
+                (Binding::PadButton(Button::Start), Action::Start),
+                (Binding::PadButton(Button::Back), Action::Back),
+            ],
+            // The usual USB dance pad layout: arrows on the first four buttons, select/start on 8/9
+            joystick: vec![
+                (Binding::JoyButton(0), lanes(0)),
+                (Binding::JoyButton(1), lanes(1)),
+                (Binding::JoyButton(2), lanes(2)),
+                (Binding::JoyButton(3), lanes(3)),
+                (Binding::JoyHat(0, HatDir::Left), lanes(0)),
+                (Binding::JoyHat(0, HatDir::Down), lanes(1)),
+                (Binding::JoyHat(0, HatDir::Up), lanes(2)),
+                (Binding::JoyHat(0, HatDir::Right), lanes(3)),
+                (Binding::JoyButton(9), Action::Start),
+                (Binding::JoyButton(8), Action::Back),
+            ],
+            devices: HashMap::new(),
+        }
+    }
+}
+
+
+impl Bindings {
+    // Loads the bindings file, writing out the defaults when there is none so they can be edited
+    pub fn load(path: &Path) -> Self {
+        let Some(tags) = config::read_tags(path) else {
+            let bindings = Self::default();
+            bindings.save(path);
+            return bindings;
+        };
+
+        let mut bindings = Self::default();
+        for (tag, value) in tags {
+            match tag.as_str() {
+                // Keys are written as keycodes, names like "," or "=" would be taken for the separators.
+                // Names still load so the file can be edited by hand.
+                "KEYS" => {
+                    bindings.keys = Self::parse_list(&value)
+                        .filter_map(|(key, action)| {
+                            let key = key.parse().ok().and_then(Keycode::from_i32).or_else(|| Keycode::from_name(key))?;
+                            Some((key, action))
+                        })
+                        .collect();
+                }
+                "PAD" => bindings.pad = Self::parse_device_list(&value),
+                "JOYSTICK" => bindings.joystick = Self::parse_device_list(&value),
+                "DEVICE" => {
+                    if let Some((guid, list)) = value.split_once(':') {
+                        bindings.devices.insert(guid.trim().to_string(), Self::parse_device_list(list));
+                    }
+                }
+                _ => {}
+            }
+        }
+        bindings
+    }
+
+    pub fn save(&self, path: &Path) {
+        let keys = self.keys.iter()
+            .map(|(key, action)| format!("{}={}", key.into_i32(), action_name(*action)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut tags = vec![
+            ("KEYS".to_string(), keys),
+            ("PAD".to_string(), Self::device_list_string(&self.pad)),
+            ("JOYSTICK".to_string(), Self::device_list_string(&self.joystick)),
+        ];
+        for (guid, list) in &self.devices {
+            tags.push(("DEVICE".to_string(), format!("{}:{}", guid, Self::device_list_string(list))));
+        }
+        config::write_tags(path, &tags);
+    }
+
+    fn key_action(&self, key: Keycode) -> Option<Action> {
+        self.keys.iter().find(|&&(k, _)| k == key).map(|&(_, action)| action)
+    }
+
+    fn device_action(&self, guid: &str, controller: bool, binding: Binding) -> Option<Action> {
+        let list = self.devices.get(guid)
+            .unwrap_or(if controller { &self.pad } else { &self.joystick });
+        list.iter().find(|&&(b, _)| b == binding).map(|&(_, action)| action)
+    }
+
+    fn parse_list(value: &str) -> impl Iterator<Item = (&str, Action)> {
+        value.split(',').filter_map(|entry| {
+            let (name, action) = entry.split_once('=')?;
+            Some((name.trim(), parse_action(action.trim())?))
+        })
+    }
+
+    fn parse_device_list(value: &str) -> Vec<(Binding, Action)> {
+        Self::parse_list(value)
+            .filter_map(|(name, action)| Some((parse_binding(name)?, action)))
+            .collect()
+    }
+
+    fn device_list_string(list: &[(Binding, Action)]) -> String {
+        list.iter()
+            .map(|(binding, action)| format!("{}={}", binding_name(*binding), action_name(*action)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+
+fn action_name(action: Action) -> String {
+    match action {
+        Action::Lane(lane) => format!("lane{}", lane),
+        Action::Start => "start".to_string(),
+        Action::Back => "back".to_string(),
+    }
+}
+
+fn parse_action(name: &str) -> Option<Action> {
+    match name {
+        "start" => Some(Action::Start),
+        "back" => Some(Action::Back),
+        _ => name.strip_prefix("lane")?.parse().ok()
+            .filter(|&lane| lane < LANES)
+            .map(Action::Lane),
+    }
+}
+
+fn binding_name(binding: Binding) -> String {
+    match binding {
+        Binding::PadButton(button) => button.string(),
+        Binding::JoyButton(n) => format!("button{}", n),
+        Binding::JoyHat(n, dir) => format!("hat{}{}", n, match dir {
+            HatDir::Up => "up",
+            HatDir::Down => "down",
+            HatDir::Left => "left",
+            HatDir::Right => "right",
+        }),
+    }
+}
+
+fn parse_binding(name: &str) -> Option<Binding> {
+    if let Some(n) = name.strip_prefix("button") {
+        return n.parse().ok().map(Binding::JoyButton);
+    }
+    if let Some(rest) = name.strip_prefix("hat") {
+        let split = rest.find(|c: char| !c.is_ascii_digit())?;
+        let dir = match &rest[split..] {
+            "up" => HatDir::Up,
+            "down" => HatDir::Down,
+            "left" => HatDir::Left,
+            "right" => HatDir::Right,
+            _ => return None,
+        };
+        return rest[..split].parse().ok().map(|n| Binding::JoyHat(n, dir));
+    }
+    Button::from_string(name).map(Binding::PadButton)
+}
+
+fn hat_dirs(state: HatState) -> Vec<HatDir> {
+    let bits = state as u8;
+    [(0x01, HatDir::Up), (0x02, HatDir::Right), (0x04, HatDir::Down), (0x08, HatDir::Left)]
+        .into_iter()
+        .filter(|&(bit, _)| bits & bit != 0)
+        .map(|(_, dir)| dir)
+        .collect()
+}
+
+
+enum Device {
+    Controller(GameController),
+    Joystick(Joystick),
+}
+
+struct OpenDevice {
+    guid: String,
+    // Only kept so SDL keeps sending events for it
+    device: Device,
+    hats: HashMap<u8, HatState>,
+}
+
+
+// Turns keyboard, controller and joystick events into lane presses.
+// Devices are opened and closed as SDL reports them plugged in or out.
+pub struct Input {
+    bindings: Bindings,
+    controller_subsystem: GameControllerSubsystem,
+    joystick_subsystem: JoystickSubsystem,
+    devices: HashMap<u32, OpenDevice>,
+    held: HashMap<Source, Action>,
+    timer: TimerSubsystem,
+}
+
+
+impl Input {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+        // Already connected devices get a DeviceAdded event at startup too
+        Self {
+            bindings: Bindings::default(),
+            controller_subsystem: sdl_context.game_controller().unwrap(),
+            joystick_subsystem: sdl_context.joystick().unwrap(),
+            devices: HashMap::new(),
+            held: HashMap::new(),
+            timer: sdl_context.timer().unwrap(),
+        }
+    }
+
+    // Seconds since SDL received the event
+    pub fn event_age(&self, event: &Event) -> f64 {
+        self.timer.ticks().wrapping_sub(event.get_timestamp()) as f64 / 1000.0
+    }
+
+    pub fn load_bindings(&mut self, path: &Path) {
+        self.bindings = Bindings::load(path);
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.held.values().any(|&a| a == action)
+    }
+
+    pub fn handle_event(&mut self, event: &Event) -> Vec<InputEvent> {
+        match *event {
+            Event::KeyDown { keycode: Some(key), .. } => {
+                let action = self.bindings.key_action(key);
+                self.press(Source::Key(key), action)
+            }
+            Event::KeyUp { keycode: Some(key), .. } => self.release(Source::Key(key)),
+
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.controller_subsystem.open(which) {
+                    Ok(controller) => self.add_device(which, Device::Controller(controller)),
+                    Err(e) => eprintln!("Failed to open controller {}: {}", which, e),
+                }
+                Vec::new()
+            }
+            Event::JoyDeviceAdded { which, .. } => {
+                // Controllers get their own event above
+                if !self.controller_subsystem.is_game_controller(which) {
+                    match self.joystick_subsystem.open(which) {
+                        Ok(joystick) => self.add_device(which, Device::Joystick(joystick)),
+                        Err(e) => eprintln!("Failed to open joystick {}: {}", which, e),
+                    }
+                }
+                Vec::new()
+            }
+            Event::ControllerDeviceRemoved { which, .. } | Event::JoyDeviceRemoved { which, .. } => {
+                self.remove_device(which)
+            }
+
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.press_device(which, Binding::PadButton(button), true)
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.release_device(which, Binding::PadButton(button), true)
+            }
+            Event::JoyButtonDown { which, button_idx, .. } => {
+                self.press_device(which, Binding::JoyButton(button_idx), false)
+            }
+            Event::JoyButtonUp { which, button_idx, .. } => {
+                self.release_device(which, Binding::JoyButton(button_idx), false)
+            }
+            Event::JoyHatMotion { which, hat_idx, state, .. } => self.hat_motion(which, hat_idx, state),
+            _ => Vec::new(),
+        }
+    }
+
+    fn add_device(&mut self, index: u32, device: Device) {
+        let guid = self.joystick_subsystem.device_guid(index)
+            .map(|guid| guid.string())
+            .unwrap_or_default();
+        let (id, name) = match &device {
+            Device::Controller(c) => (c.instance_id(), c.name()),
+            Device::Joystick(j) => (j.instance_id(), j.name()),
+        };
+
+        eprintln!("Connected {} ({})", name, guid);
+        self.devices.insert(id, OpenDevice { guid, device, hats: HashMap::new() });
+    }
+
+    fn remove_device(&mut self, id: u32) -> Vec<InputEvent> {
+        if self.devices.remove(&id).is_none() {
+            return Vec::new();
+        }
+
+        let sources: Vec<Source> = self.held.keys()
+            .filter(|source| matches!(source, Source::Device(device, _) if *device == id))
+            .copied()
+            .collect();
+        sources.into_iter().flat_map(|source| self.release(source)).collect()
+    }
+
+    // Controllers also send raw joystick events, only listen to the kind the device was opened as
+    fn device_action(&self, id: u32, binding: Binding, controller: bool) -> Option<Option<Action>> {
+        let device = self.devices.get(&id)?;
+        if matches!(device.device, Device::Controller(_)) != controller {
+            return None;
+        }
+        Some(self.bindings.device_action(&device.guid, controller, binding))
+    }
+
+    fn press_device(&mut self, id: u32, binding: Binding, controller: bool) -> Vec<InputEvent> {
+        match self.device_action(id, binding, controller) {
+            Some(action) => self.press(Source::Device(id, binding), action),
+            None => Vec::new(),
+        }
+    }
+
+    fn release_device(&mut self, id: u32, binding: Binding, controller: bool) -> Vec<InputEvent> {
+        if self.device_action(id, binding, controller).is_none() {
+            return Vec::new();
+        }
+        self.release(Source::Device(id, binding))
+    }
+
+    fn hat_motion(&mut self, id: u32, hat: u8, state: HatState) -> Vec<InputEvent> {
+        let Some(device) = self.devices.get_mut(&id) else {
+            return Vec::new();
+        };
+        let old = device.hats.insert(hat, state).unwrap_or(HatState::Centered);
+        let (old, new) = (hat_dirs(old), hat_dirs(state));
+
+        let mut events = Vec::new();
+        for dir in old.iter().filter(|dir| !new.contains(dir)) {
+            events.extend(self.release_device(id, Binding::JoyHat(hat, *dir), false));
+        }
+        for dir in new.iter().filter(|dir| !old.contains(dir)) {
+            events.extend(self.press_device(id, Binding::JoyHat(hat, *dir), false));
+        }
+        events
+    }
+
+    // The same action can be held from several sources (a key and a pad arrow),
+    // it only counts as pressed once and released when the last one lets go
+    fn press(&mut self, source: Source, action: Option<Action>) -> Vec<InputEvent> {
+        let Some(action) = action else {
+            return Vec::new();
+        };
+        if self.held.contains_key(&source) {
+            return Vec::new();
+        }
+
+        let already_held = self.is_held(action);
+        self.held.insert(source, action);
+        if already_held {
+            Vec::new()
+        } else {
+            vec![InputEvent::Pressed(action)]
+        }
+    }
+
+    fn release(&mut self, source: Source) -> Vec<InputEvent> {
+        match self.held.remove(&source) {
+            Some(action) if !self.is_held(action) => vec![InputEvent::Released(action)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separator_keys_round_trip() {
+        let path = std::env::temp_dir().join(format!("rustrhythm-bindings-test-{}.cfg", std::process::id()));
+        let mut bindings = Bindings::default();
+        bindings.keys.push((Keycode::Comma, Action::Lane(0)));
+        bindings.keys.push((Keycode::Equals, Action::Lane(3)));
+        bindings.keys.push((Keycode::Semicolon, Action::Start));
+        bindings.save(&path);
+
+        let loaded = Bindings::load(&path);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded.keys, bindings.keys);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::game_state::Arrow;
+use crate::input::LANES;
+use crate::score::{Judgment, PlayResult};
+
+// Timing windows in seconds, either side of the note
+pub const FLAWLESS_WINDOW: f64 = 0.0225;
+pub const PERFECT_WINDOW: f64 = 0.045;
+pub const GOOD_WINDOW: f64 = 0.090;
+pub const OK_WINDOW: f64 = 0.135;
+
+// Letting go of a hold this close to its end still counts as holding it through
+const HOLD_RELEASE_WINDOW: f64 = 0.1;
+// A roll drops when it goes this long without a tap
+const ROLL_WINDOW: f64 = 0.5;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteState {
+    Pending,
+    // Hold whose head was hit and is still held down, or roll that is still being tapped
+    Holding,
+    // Hit tap, finished hold or exploded mine, no longer drawn
+    Cleared,
+    // Missed tap, dropped hold or passed mine, drawn until it scrolls away
+    Missed,
+}
+
+
+// A judged tap or missed note, for on-screen feedback
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub lane: usize,
+    pub judgment: Judgment,
+    pub offset: f64, // seconds late, negative when early, 0 for misses
+    pub time: f64, // chart time it was judged at
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub arrow: Arrow,
+    pub state: NoteState,
+    last_tap: f64, // rolls only
+}
+
+
+// Judges a chart from lane presses and releases stamped with chart time.
+// The outcome only depends on the order and times of those events, never on
+// when update() happens to be called, so a recorded play replays to the same score.
+pub struct Judge {
+    notes: Vec<Note>,
+    held: [bool; LANES],
+    pub result: PlayResult,
+    pub last_hit: Option<Hit>,
+}
+
+
+impl Judge {
+    pub fn new(arrows: &[Arrow]) -> Self {
+        let mut notes: Vec<Note> = arrows.iter()
+            .filter(|arrow| arrow.lane < LANES)
+            .map(|arrow| Note { arrow: arrow.clone(), state: NoteState::Pending, last_tap: 0.0 })
+            .collect();
+        notes.sort_by(|a, b| a.arrow.hit_time.total_cmp(&b.arrow.hit_time));
+
+        Self {
+            notes,
+            held: [false; LANES],
+            result: PlayResult::default(),
+            last_hit: None,
+        }
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub fn is_held(&self, lane: usize) -> bool {
+        self.held.get(lane).copied().unwrap_or(false)
+    }
+
+    // Whether a hold or roll in the lane is being held right now
+    pub fn is_holding(&self, lane: usize) -> bool {
+        self.notes.iter().any(|note| note.arrow.lane == lane && note.state == NoteState::Holding)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.notes.iter().all(|note| matches!(note.state, NoteState::Cleared | NoteState::Missed))
+    }
+
+    // Resolves everything that is decided by `time` having passed
+    pub fn update(&mut self, time: f64) {
+        for note in &mut self.notes {
+            let arrow = &note.arrow;
+            match note.state {
+                NoteState::Pending if arrow.mine => {
+                    if arrow.hit_time <= time {
+                        if self.held[arrow.lane] {
+                            self.result.mines_hit += 1;
+                            note.state = NoteState::Cleared;
+                        } else {
+                            note.state = NoteState::Missed;
+                        }
+                    }
+                }
+                NoteState::Pending => {
+                    if arrow.hit_time + OK_WINDOW < time {
+                        self.result.judge(Judgment::Miss);
+                        self.last_hit = Some(Hit { lane: arrow.lane, judgment: Judgment::Miss, offset: 0.0, time: arrow.hit_time + OK_WINDOW });
+                        if arrow.long {
+                            self.result.hold(false);
+                        }
+                        note.state = NoteState::Missed;
+                    }
+                }
+                NoteState::Holding => {
+                    if arrow.roll && note.last_tap + ROLL_WINDOW < time.min(arrow.end_time) {
+                        self.result.hold(false);
+                        note.state = NoteState::Missed;
+                    } else if arrow.end_time <= time {
+                        self.result.hold(true);
+                        note.state = NoteState::Cleared;
+                    }
+                }
+                NoteState::Cleared | NoteState::Missed => {}
+            }
+        }
+    }
+
+    // Hits the earliest note in the lane that is within the timing windows
+    pub fn press(&mut self, lane: usize, time: f64) -> Option<Hit> {
+        if lane >= LANES {
+            return None;
+        }
+        self.update(time);
+        self.held[lane] = true;
+
+        // Tapping a roll keeps it going
+        if let Some(roll) = self.notes.iter_mut()
+            .find(|note| note.arrow.lane == lane && note.arrow.roll && note.state == NoteState::Holding) {
+            roll.last_tap = time;
+            return None;
+        }
+
+        let note = self.notes.iter_mut()
+            .filter(|note| note.arrow.lane == lane && !note.arrow.mine && note.state == NoteState::Pending)
+            .find(|note| (note.arrow.hit_time - time).abs() <= OK_WINDOW)?;
+
+        let offset = time - note.arrow.hit_time;
+        let judgment = match offset.abs() {
+            d if d <= FLAWLESS_WINDOW => Judgment::Flawless,
+            d if d <= PERFECT_WINDOW => Judgment::Perfect,
+            d if d <= GOOD_WINDOW => Judgment::Good,
+            _ => Judgment::Ok,
+        };
+        note.state = if note.arrow.long { NoteState::Holding } else { NoteState::Cleared };
+        note.last_tap = time;
+        self.result.judge(judgment);
+        self.result.offsets.push(offset);
+        self.last_hit = Some(Hit { lane, judgment, offset, time });
+        self.last_hit
+    }
+
+    pub fn release(&mut self, lane: usize, time: f64) {
+        if lane >= LANES {
+            return;
+        }
+        self.update(time);
+        self.held[lane] = false;
+
+        for note in &mut self.notes {
+            if note.arrow.lane == lane && !note.arrow.roll && note.state == NoteState::Holding {
+                let ok = time >= note.arrow.end_time - HOLD_RELEASE_WINDOW;
+                self.result.hold(ok);
+                note.state = if ok { NoteState::Cleared } else { NoteState::Missed };
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrow(lane: usize, hit_time: f64) -> Arrow {
+        Arrow { lane, hit_time, spawn_time: hit_time - 1.0, long: false, end_time: 0.0, mine: false, roll: false, row: 0, quantization: 4 }
+    }
+
+    fn hold(lane: usize, hit_time: f64, end_time: f64) -> Arrow {
+        Arrow { long: true, end_time, ..arrow(lane, hit_time) }
+    }
+
+    #[test]
+    fn timing_windows() {
+        let mut judge = Judge::new(&[arrow(0, 1.0), arrow(1, 2.0), arrow(2, 3.0), arrow(3, 4.0), arrow(0, 5.0)]);
+
+        assert_eq!(judge.press(0, 1.0).map(|hit| hit.judgment), Some(Judgment::Flawless));
+        assert_eq!(judge.press(1, 2.04).map(|hit| hit.judgment), Some(Judgment::Perfect));
+        assert_eq!(judge.press(2, 2.92).map(|hit| hit.judgment), Some(Judgment::Good));
+        assert_eq!(judge.press(3, 4.1).map(|hit| hit.judgment), Some(Judgment::Ok));
+        // Too early to hit anything
+        assert!(judge.press(0, 4.5).is_none());
+        judge.update(6.0);
+
+        assert_eq!(judge.result.count(Judgment::Miss), 1);
+        assert_eq!(judge.result.max_combo, 4);
+        assert_eq!(judge.result.offsets.len(), 4);
+        assert!((judge.result.offsets[2] + 0.08).abs() < 1e-9);
+        assert!(judge.finished());
+    }
+
+    #[test]
+    fn holds() {
+        let mut judge = Judge::new(&[hold(0, 1.0, 2.0), hold(1, 1.0, 2.0)]);
+        judge.press(0, 1.0);
+        judge.press(1, 1.0);
+        judge.release(0, 1.5);
+        judge.release(1, 1.95);
+        judge.update(3.0);
+
+        assert_eq!(judge.result.holds_ng, 1);
+        assert_eq!(judge.result.holds_ok, 1);
+    }
+
+    #[test]
+    fn mines() {
+        let mine = |lane| Arrow { mine: true, ..arrow(lane, 1.0) };
+        let mut judge = Judge::new(&[mine(0), mine(1)]);
+        judge.press(0, 0.9);
+        judge.update(1.1);
+
+        assert_eq!(judge.result.mines_hit, 1);
+        assert_eq!(judge.result.count(Judgment::Miss), 0);
+    }
+}
@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cache::SongCache;
+use crate::game_state::GameState;
+use crate::highscores::ScoreDb;
+
+
+// What the song wheel needs to know about a simfile without keeping its notes around
+#[derive(Debug, Clone, Default)]
+pub struct Song {
+    pub dir: PathBuf,
+    pub sm_file: PathBuf,
+    pub group: String,
+
+    pub title: String,
+    pub subtitle: String,
+    pub artist: String,
+    pub title_translit: String,
+    pub subtitle_translit: String,
+    pub artist_translit: String,
+    pub genre: String,
+    pub credit: String,
+    pub music: String,
+    pub banner: String,
+    pub cdtitle: String,
+    pub display_bpm: String,
+    pub selectable: bool,
+
+    pub steps_types: Vec<String>,
+    pub difficulties: Vec<String>,
+    pub meters: Vec<u32>,
+    pub chart_hashes: Vec<u64>,
+    pub note_counts: Vec<usize>,
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    pub length: f64,
+
+    pub sample_start: f64,
+    pub sample_len: f64,
+}
+
+
+impl Song {
+    pub fn from_game_state(dir: &Path, sm_file: &Path, group: &str, game_state: &GameState) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            sm_file: sm_file.to_path_buf(),
+            group: group.to_string(),
+            title: game_state.title.clone(),
+            subtitle: game_state.subtitle.clone(),
+            artist: game_state.artist.clone(),
+            title_translit: game_state.title_translit.clone(),
+            subtitle_translit: game_state.subtitle_translit.clone(),
+            artist_translit: game_state.artist_translit.clone(),
+            genre: game_state.genre.clone(),
+            credit: game_state.credit.clone(),
+            music: game_state.song.clone(),
+            banner: game_state.banner.clone(),
+            cdtitle: game_state.cdtitle.clone(),
+            display_bpm: game_state.display_bpm.clone(),
+            selectable: game_state.selectable,
+            steps_types: game_state.steps_types.clone(),
+            difficulties: game_state.difficulties.clone(),
+            meters: game_state.meters.clone(),
+            chart_hashes: game_state.chart_hashes.clone(),
+            note_counts: game_state.note_counts(),
+            min_bpm: game_state.min_bpm,
+            max_bpm: game_state.max_bpm,
+            length: game_state.length(),
+            sample_start: game_state.sample_start,
+            sample_len: game_state.sample_len,
+        }
+    }
+
+    pub fn display_title(&self, translit: bool) -> &str {
+        display_name(&self.title, &self.title_translit, translit)
+    }
+
+    pub fn display_subtitle(&self, translit: bool) -> &str {
+        display_name(&self.subtitle, &self.subtitle_translit, translit)
+    }
+
+    pub fn display_artist(&self, translit: bool) -> &str {
+        display_name(&self.artist, &self.artist_translit, translit)
+    }
+
+    // #DISPLAYBPM when the simfile has one, the range of its #BPMS otherwise
+    pub fn bpm_string(&self) -> String {
+        if self.display_bpm == "*" {
+            return "??? BPM".to_string();
+        }
+        let parse = |value: &str| value.trim().parse::<f64>().ok();
+        let (min, max) = match self.display_bpm.split_once(':') {
+            Some((min, max)) => (parse(min), parse(max)),
+            None => (parse(&self.display_bpm), parse(&self.display_bpm)),
+        };
+        let (min, max) = min.zip(max).unwrap_or((self.min_bpm, self.max_bpm));
+
+        if (max - min).abs() < 0.5 {
+            format!("{:.0} BPM", max)
+        } else {
+            format!("{:.0}-{:.0} BPM", min, max)
+        }
+    }
+
+    // Meters of the charts that pass the steps type filter
+    fn meters_of<'a>(&'a self, steps_type: Option<&'a str>) -> impl Iterator<Item = u32> + 'a {
+        self.meters.iter().enumerate()
+            .filter(move |&(i, _)| steps_type.is_none_or(|t| self.steps_types.get(i).is_some_and(|s| s == t)))
+            .map(|(_, &meter)| meter)
+    }
+
+    fn matches_query(&self, query: &str) -> bool {
+        let haystack = [
+            &self.title, &self.subtitle, &self.artist,
+            &self.title_translit, &self.subtitle_translit, &self.artist_translit,
+        ].map(|field| field.to_lowercase());
+
+        query.to_lowercase()
+            .split_whitespace()
+            .all(|word| haystack.iter().any(|field| field.contains(word)))
+    }
+}
+
+
+// The transliterated form of a name when it is preferred and the simfile has one
+pub fn display_name<'a>(name: &'a str, translit: &'a str, prefer_translit: bool) -> &'a str {
+    if prefer_translit && !translit.is_empty() { translit } else { name }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Group,
+    Title,
+    Artist,
+    Bpm,
+    Meter,
+    MostPlayed,
+}
+
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Group => SortOrder::Title,
+            SortOrder::Title => SortOrder::Artist,
+            SortOrder::Artist => SortOrder::Bpm,
+            SortOrder::Bpm => SortOrder::Meter,
+            SortOrder::Meter => SortOrder::MostPlayed,
+            SortOrder::MostPlayed => SortOrder::Group,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Group => "Group",
+            SortOrder::Title => "Title",
+            SortOrder::Artist => "Artist",
+            SortOrder::Bpm => "BPM",
+            SortOrder::Meter => "Meter",
+            SortOrder::MostPlayed => "Most played",
+        }
+    }
+}
+
+
+// Everything is optional, None lets every song through
+#[derive(Debug, Clone, Default)]
+pub struct SongFilter {
+    pub query: String,
+    pub steps_type: Option<String>,
+    pub min_meter: Option<u32>,
+    pub max_meter: Option<u32>,
+    pub min_bpm: Option<f64>,
+    pub max_bpm: Option<f64>,
+    pub min_length: Option<f64>,
+    pub max_length: Option<f64>,
+}
+
+
+impl SongFilter {
+    pub fn matches(&self, song: &Song) -> bool {
+        let steps_type = self.steps_type.as_deref();
+        let meter_ok = song.meters_of(steps_type).any(|meter| {
+            self.min_meter.is_none_or(|min| meter >= min) && self.max_meter.is_none_or(|max| meter <= max)
+        });
+
+        song.selectable
+            && meter_ok
+            && self.min_bpm.is_none_or(|min| song.min_bpm >= min)
+            && self.max_bpm.is_none_or(|max| song.max_bpm <= max)
+            && self.min_length.is_none_or(|min| song.length >= min)
+            && self.max_length.is_none_or(|max| song.length <= max)
+            && (self.query.is_empty() || song.matches_query(&self.query))
+    }
+}
+
+
+pub struct Library {
+    pub songs: Vec<Song>,
+}
+
+
+impl Library {
+    // Scans a Songs/<Group>/<Song>/ tree. Song folders placed straight in the root
+    // are accepted too and get the root folder's name as their group.
+    pub fn scan(root: &Path, cache: &mut SongCache) -> Self {
+        let mut songs = Vec::new();
+        let root_name = Self::dir_name(root);
+
+        for dir in Self::sub_dirs(root) {
+            if let Some(song) = Self::load_song(&dir, &root_name, cache) {
+                songs.push(song);
+                continue;
+            }
+
+            let group = Self::dir_name(&dir);
+            for song_dir in Self::sub_dirs(&dir) {
+                songs.extend(Self::load_song(&song_dir, &group, cache));
+            }
+        }
+
+        // A single song folder was given instead of a library
+        if songs.is_empty() {
+            songs.extend(Self::load_song(root, &root_name, cache));
+        }
+
+        songs.sort_by_key(|song| (song.group.to_lowercase(), song.title.to_lowercase()));
+        Self { songs }
+    }
+
+    // Indices of the songs passing the filter, in the given order. Titles and artists
+    // sort by the names shown, transliterated ones when `translit` is set.
+    pub fn select(&self, filter: &SongFilter, sort: SortOrder, scores: &ScoreDb, translit: bool) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.songs.len())
+            .filter(|&i| filter.matches(&self.songs[i]))
+            .collect();
+
+        let steps_type = filter.steps_type.as_deref();
+        let lower = |text: &str| text.to_lowercase();
+
+        // The library is already in group order, stable sorts keep it as the tiebreaker
+        match sort {
+            SortOrder::Group => {}
+            SortOrder::Title => indices.sort_by_key(|&i| lower(self.songs[i].display_title(translit))),
+            SortOrder::Artist => indices.sort_by_key(|&i| lower(self.songs[i].display_artist(translit))),
+            SortOrder::Bpm => indices.sort_by(|&a, &b| self.songs[a].max_bpm.total_cmp(&self.songs[b].max_bpm)),
+            SortOrder::Meter => indices.sort_by_key(|&i| self.songs[i].meters_of(steps_type).max().unwrap_or(0)),
+            SortOrder::MostPlayed => indices.sort_by_key(|&i| std::cmp::Reverse(scores.play_count(&self.songs[i].chart_hashes))),
+        }
+        indices
+    }
+
+    // Steps types found in the library, for the filter to cycle through
+    pub fn steps_types(&self) -> Vec<String> {
+        let mut types: Vec<String> = self.songs.iter()
+            .flat_map(|song| song.steps_types.iter().cloned())
+            .collect();
+        types.sort();
+        types.dedup();
+        types
+    }
+
+    pub fn find_sm_file(dir: &Path) -> Option<PathBuf> {
+        fs::read_dir(dir).ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sm")))
+    }
+
+    fn load_song(dir: &Path, group: &str, cache: &mut SongCache) -> Option<Song> {
+        let sm_file = Self::find_sm_file(dir)?;
+        let mut song = cache.song(&sm_file)?;
+        song.dir = dir.to_path_buf();
+        song.group = group.to_string();
+        Some(song)
+    }
+
+    fn sub_dirs(dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let mut dirs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        dirs.sort();
+        dirs
+    }
+
+    fn dir_name(dir: &Path) -> String {
+        dir.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
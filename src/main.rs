@@ -2,27 +2,74 @@ use rfd::FileDialog;
 use sdl2::render::{TextureQuery, BlendMode};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use std::time::{Duration, Instant};
 use sdl2::mixer::{InitFlag, Music};
 use sdl2::image::LoadTexture;
-use std::collections::HashSet;
-use std::{fs, env};
+use std::env;
+use std::path::{Path, PathBuf};
 
 
+mod assets;
+mod audio;
+mod cache;
+mod clock;
+mod config;
+mod display;
+mod frame;
 mod game_state;
+mod highscores;
+mod input;
+mod judge;
+mod library;
+mod modifiers;
+mod noteskin;
+mod playfield;
+mod practice;
+mod profile;
+mod replay;
+mod score;
+mod text;
+mod theme;
+mod timing;
+use audio::Track;
+use cache::SongCache;
+use frame::FramePacer;
 use game_state::GameState;
+use highscores::{PlayRecord, ScoreDb};
+use input::{Action, Input, InputEvent, LANES};
+use judge::{Hit, Judge, NoteState};
+use library::{Library, SongFilter, SortOrder};
+use modifiers::Modifiers;
+use noteskin::Noteskin;
+use playfield::{Playfield, NOTE_HEIGHT};
+use practice::Practice;
+use profile::Profile;
+use replay::Replay;
+use score::{Judgment, PlayResult};
+use text::TextCache;
+use theme::Theme;
+
+
+const SONGS_DIR: &str = "./songs";
+const NOTESKINS_DIR: &str = "./noteskins";
+const THEMES_DIR: &str = "./themes";
+// Counted down before play picks up again after a pause
+const RESUME_COUNTDOWN: Duration = Duration::from_secs(3);
+// Seconds a receptor glows after a hit
+const RECEPTOR_GLOW: f64 = 0.15;
+// Seconds hit feedback stays on screen
+const EXPLOSION_TIME: f64 = 0.25;
+const JUDGMENT_TIME: f64 = 0.8;
+const COMBO_POP: f64 = 0.12;
+// Hits shown on the error bar
+const ERROR_BAR_HITS: usize = 20;
 
 
 fn main() {
 
     // CONFIGS
-    let map_path = FileDialog::new().set_directory(env::current_dir().expect("Failed to get current directory"))
-        .set_title("Select a folder with an .sm file in it")
-        .pick_folder()
-        .expect("Choose a folder");
-
-    let game_speed = 1.5; // Higher = slower
     let note_gap = 25; // px between the notes
 
 
@@ -30,341 +77,1480 @@ fn main() {
     let video_subsystem = sdl_context.video().unwrap();
 
 
-
-    
-
     sdl2::mixer::open_audio(44100, sdl2::mixer::DEFAULT_FORMAT, 2, 1024).unwrap();
     let _mixer_context = sdl2::mixer::init(InitFlag::MP3).unwrap();
     sdl2::mixer::allocate_channels(4);
+    sdl2::mixer::reserve_channels(1); // audio::MUSIC_CHANNEL
 
 
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string()).unwrap();
-    let font = ttf_context.load_font("./assets/OpenSans.ttf", 32).unwrap();
-
-
-    let paths = fs::read_dir(map_path.clone()).unwrap();
-    let mut sm_file = String::new(); 
-    for path in paths {
-        let path = path.unwrap();
-        let file_name = path.file_name().into_string().unwrap_or(String::new()); 
-
-        if file_name.ends_with(".sm") {
-            sm_file = path.path().to_str().unwrap_or_default().to_string();
-        }
+    // --theme=<name> restyles the game from themes/<name>/theme.txt
+    let theme = match env::args().find_map(|arg| arg.strip_prefix("--theme=").map(str::to_string)) {
+        Some(name) => Theme::load(&Path::new(THEMES_DIR).join(name)),
+        None => Theme::default(),
+    };
+    let load_font = |size| ttf_context.load_font(&theme.font, size).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {}, using the default font", theme.font.display(), e);
+        ttf_context.load_font(Theme::default().font, size).unwrap()
+    });
+    let font = load_font(theme.font_size);
+    let small_font = load_font(theme.small_font_size);
+
+
+    // --rebuild-cache re-parses every simfile instead of trusting data/song_cache.txt
+    let rebuild_cache = env::args().any(|arg| arg == "--rebuild-cache");
+    let mut cache = SongCache::load(rebuild_cache);
+    // --autoplay plays every chart perfectly by itself, nothing it plays is saved
+    let autoplay = env::args().any(|arg| arg == "--autoplay");
+
+    let mut library = Library::scan(Path::new(SONGS_DIR), &mut cache);
+    if library.songs.is_empty() {
+        let songs_path = FileDialog::new().set_directory(env::current_dir().expect("Failed to get current directory"))
+            .set_title("Select a songs folder or a folder with an .sm file in it")
+            .pick_folder()
+            .expect("Choose a folder");
+        library = Library::scan(&songs_path, &mut cache);
+    }
+    cache.save();
+    eprintln!("Loaded {} songs ({} parsed, rest from cache)", library.songs.len(), cache.parsed());
+    if library.songs.is_empty() {
+        eprintln!("No .sm files found");
+        return;
     }
 
-    let mut game_state = GameState::new(&sm_file, game_speed);
-
-    let song_name = map_path.clone().join(&game_state.song);
-
-
-    let music = Music::from_file(song_name).expect("Failed to load music file");
-
-    let window_title = &(game_state.artist.clone() + " - " + &game_state.title);
 
-    let window = video_subsystem.window(window_title, 800, 600)
+    let window = video_subsystem.window("rustrhythm", display::WIDTH, display::HEIGHT)
         .position_centered()
+        .resizable()
         .vulkan()
         .build()
         .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
+    // --no-vsync paces frames with a timer instead of waiting for the display
+    let mut canvas = if env::args().any(|arg| arg == "--no-vsync") {
+        window.into_canvas().build().unwrap()
+    } else {
+        window.into_canvas().present_vsync().build().unwrap()
+    };
     canvas.set_blend_mode(BlendMode::Blend);
+    // --fullscreen starts in borderless fullscreen, F11 switches at any time
+    display::setup(&mut canvas, env::args().any(|arg| arg == "--fullscreen"));
     let texture_creator = canvas.texture_creator();
+    // Shared by every screen, text they have in common is only rendered once
+    let mut text_cache = TextCache::new(&texture_creator);
+    let mut pacer = FramePacer::new(&canvas);
 
 
-    let bg_name = map_path.join(&game_state.bg); 
-    let background_img = texture_creator.load_texture(&bg_name).unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut input = Input::new(&sdl_context);
+
+    let Some(mut profile) = choose_profile(&mut canvas, &mut event_pump, &mut input, &mut text_cache, &mut pacer, &font, &small_font, &theme) else {
+        return;
+    };
+    input.load_bindings(&profile.bindings_path());
+    let mut noteskin = Noteskin::load(&texture_creator, &Path::new(NOTESKINS_DIR).join(&profile.noteskin));
+
+    let mut scores = ScoreDb::load(&profile.scores_path());
+    let mut wheel = WheelState { selected: 0, filter: SongFilter::default(), sort: SortOrder::Group, translit: profile.translit, error_bar: profile.error_bar };
+
+    loop {
+        canvas.window_mut().set_title("rustrhythm").unwrap();
+        let song_index = choose_song(&mut canvas, &mut event_pump, &mut input, &mut text_cache, &mut pacer, &font, &small_font, &theme, &library, &scores, &mut wheel, &mut profile.modifiers);
+        profile.translit = wheel.translit;
+        profile.error_bar = wheel.error_bar;
+        profile.save();
+        let Some(song_index) = song_index else {
+            break;
+        };
+        let song = library.songs[song_index].clone();
+
+        let mut game_state = GameState::new(song.sm_file.to_str().unwrap_or_default(), profile.speed, profile.offset);
+
+        // Songs whose music can't be found play silently, and ones without a background get a generated one
+        let music_path = assets::find_music(&song.dir, &game_state.song);
+        let music = music_path.as_ref().and_then(|path| {
+            Music::from_file(path).map_err(|e| eprintln!("Failed to load {}: {}", path.display(), e)).ok()
+        });
 
+        let song_title = format!("{} - {}", song.display_artist(profile.translit), song.display_title(profile.translit));
+        canvas.window_mut().set_title(&song_title).unwrap();
+
+        let background_img = assets::find_background(&song.dir, &game_state.bg, &song.banner, &song.cdtitle)
+            .and_then(|path| texture_creator.load_texture(&path).map_err(|e| eprintln!("Failed to load {}: {}", path.display(), e)).ok())
+            .unwrap_or_else(|| assets::generated_background(&texture_creator, &theme, &game_state.title));
+
+
+        let choice = choose_difficulty(&mut canvas, &mut event_pump, &mut input, &mut text_cache, &mut pacer, &font, &small_font, &theme, &game_state, &background_img, music.as_ref(), &profile.replays_path(), &mut profile.modifiers);
+        profile.save();
+        let (difficulty, mut playback, practice) = match choice {
+            Some(DifficultyChoice::Play(difficulty)) => (difficulty, None, None),
+            Some(DifficultyChoice::Watch(difficulty, replay)) => (difficulty, Some(replay), None),
+            Some(DifficultyChoice::Practice(difficulty)) => {
+                let measures = game_state.measure_counts.get(difficulty as usize).copied().unwrap_or(0);
+                let practice = Practice::new(measures, profile.modifiers);
+                let Some(practice) = choose_section(&mut canvas, &mut event_pump, &mut input, &mut text_cache, &mut pacer, &font, &small_font, &theme, &game_state, difficulty, &background_img, practice) else {
+                    continue;
+                };
+                (difficulty, None, Some(practice))
+            }
+            None => continue,
+        };
+
+        let chart = game_state.chart_hashes.get(difficulty as usize).copied().unwrap_or(0);
+        let mut track: Option<(Modifiers, Track)> = None;
+
+        loop {
+            // Replays play back with the modifiers they were recorded with
+            let mut modifiers = match (&playback, &practice) {
+                (Some(replay), _) => Modifiers::from_tag(&replay.modifiers),
+                (None, Some(practice)) => practice.modifiers,
+                (None, None) => profile.modifiers,
+            };
+            if playback.is_none() {
+                modifiers.seed = modifiers::new_seed();
+            }
+            if track.as_ref().is_none_or(|(loaded, _)| !loaded.same_audio(&modifiers)) {
+                let loaded = match &music_path {
+                    Some(path) => Track::load(path, &modifiers).unwrap_or_else(|e| {
+                        eprintln!("Failed to load {}: {}", path.display(), e);
+                        Track::Silent
+                    }),
+                    None => Track::Silent,
+                };
+                track = Some((modifiers, loaded));
+            }
+            let Some((_, track)) = &mut track else {
+                break;
+            };
+
+            match &practice {
+                Some(practice) => game_state.start_section(difficulty, &modifiers, practice.from, practice.to),
+                None => game_state.start(difficulty, &modifiers),
+            }
+            let play_started = Instant::now();
+
+            let (mode, mut replay) = match playback.take() {
+                Some(replay) => (PlayMode::Replay, replay),
+                None if practice.is_some() => (PlayMode::Practice, Replay::new(chart, &modifiers.to_tag())),
+                None if autoplay => (PlayMode::Autoplay, Replay::autoplay(chart, &modifiers.to_tag(), &game_state.arrows)),
+                None => (PlayMode::Live, Replay::new(chart, &modifiers.to_tag())),
+            };
+
+            let outcome = play(&mut canvas, &mut event_pump, &mut input, &mut text_cache, &mut pacer, &font, &small_font, &theme, &mut game_state, &background_img, track, &mut noteskin, note_gap, profile.error_bar, &mut replay, mode);
+            Track::halt();
+            let result = match outcome {
+                PlayOutcome::Finished(result) => result,
+                PlayOutcome::Restart => {
+                    if mode == PlayMode::Replay {
+                        playback = Some(replay);
+                    }
+                    continue;
+                }
+                PlayOutcome::Quit => break,
+            };
+            // Practice loops the section until the player backs out
+            if mode == PlayMode::Practice {
+                continue;
+            }
 
+            let previous_best = scores.best(chart, &modifiers).cloned();
+            if mode == PlayMode::Live {
+                scores.record(chart, &result, &replay.modifiers);
+                profile.record_play(&result, play_started.elapsed().as_secs_f64());
+                replay.save(&profile.replays_path());
+            }
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+            let difficulty_name = game_state.difficulties.get(difficulty as usize).map(String::as_str).unwrap_or_default();
+            match show_results(&mut canvas, &mut event_pump, &mut input, &mut text_cache, &mut pacer, &font, &small_font, &theme, &song_title, difficulty_name, &background_img, &result, previous_best.as_ref()) {
+                ResultsChoice::Retry => {}
+                ResultsChoice::WatchReplay => playback = Some(replay),
+                ResultsChoice::SongSelect => break,
+            }
+        }
+    }
 
-    let difficulty = choose_difficulty(&mut canvas, &mut event_pump, &font, &game_state, &background_img, &music);
+}
 
-    game_state.start(difficulty);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlayMode {
+    Live,
+    // Inputs come from a recorded replay
+    Replay,
+    // Inputs come from Replay::autoplay
+    Autoplay,
+    // A looping section that doesn't count for scores
+    Practice,
+}
 
 
-    let judgment_line_y: f64 = 550.0;
-    let spawn_y: f64 = -50.0;
-    let mut score = 0;
-    let mut combo = 0;
+enum PlayOutcome {
+    Finished(PlayResult),
+    // Picked from the pause menu
+    Restart,
+    Quit,
+}
 
-    let key_to_lane = [
-        (Keycode::D, 0),
-        (Keycode::F, 1),
-        (Keycode::J, 2),
-        (Keycode::K, 3),
-    ];
 
-    let mut held_keys: HashSet<usize> = HashSet::new();
+// Plays the chart started in game_state. Live plays record their inputs into `replay`,
+// otherwise the inputs come from `replay`.
+#[allow(clippy::too_many_arguments)]
+fn play(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+        event_pump: &mut sdl2::EventPump,
+        input: &mut Input,
+        text_cache: &mut TextCache,
+        pacer: &mut FramePacer,
+        font: &sdl2::ttf::Font,
+        small_font: &sdl2::ttf::Font,
+        theme: &Theme,
+        game_state: &mut GameState,
+        background_img: &sdl2::render::Texture<'_>,
+        track: &mut Track,
+        noteskin: &mut Noteskin,
+        note_gap: i32,
+        error_bar: bool, // the profile's preference
+        replay: &mut Replay,
+        mode: PlayMode,
+       ) -> PlayOutcome {
+
+
+    let modifiers = Modifiers::from_tag(&replay.modifiers);
+    let playfield = Playfield::new(&modifiers, theme);
+    // Last hit in each lane, for the receptor glow and explosion
+    let mut lane_hits: [Option<Hit>; LANES] = [None; LANES];
+    // Combo and when it last changed, for its pop
+    let mut combo_changed = (0, f64::NEG_INFINITY);
+    let mut judge = Judge::new(&game_state.arrows);
+    let mut next_replay_event = 0;
+    let playback = matches!(mode, PlayMode::Replay | PlayMode::Autoplay);
 
     let mut music_started = false;
 
+    // Selected row while the pause menu is open
+    let mut pause_menu: Option<usize> = None;
+    let pause_options = ["Resume", "Restart", "Quit to song select"];
+    // When play picks up again after a pause
+    let mut resume_at: Option<Instant> = None;
+    let mut next_pause = 0;
+    // Chart time everything up to has been judged
+    let mut judged_until = f64::NEG_INFINITY;
+
 
-    'running: loop {
-        if !music_started && Instant::now() >= game_state.start_time {
-            music.play(0).expect("Failed to play music");
+    loop {
+        // Chart time, negative during the lead-in
+        let elapsed_time = game_state.clock.time();
+
+        if !music_started && elapsed_time >= game_state.music_start {
+            track.play_from(game_state.music_start).expect("Failed to play music");
             music_started = true;
         }
 
 
         // Handle inputs
-        let mut pressed_keys = HashSet::new();
         for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'running;
+            if let Event::Quit { .. } = event {
+                std::process::exit(0);
+            }
+            display::handle_event(canvas, &event);
+            pacer.handle_event(&event);
+
+            // Judged at the time SDL saw the event rather than at the start of the frame.
+            // Never earlier than what was already judged, nor while the clock is stopped.
+            let event_time = if game_state.clock.is_paused() {
+                elapsed_time
+            } else {
+                (game_state.clock.time() - input.event_age(&event)).max(judged_until)
+            };
+
+            for input_event in input.handle_event(&event) {
+                // Letting go of a lane always counts, even while paused, so no hold stays held by mistake
+                if let InputEvent::Released(Action::Lane(lane)) = input_event && !playback {
+                    judge.release(lane, event_time);
+                    replay.record(event_time, lane, false);
+                    continue;
                 }
-                Event::KeyDown { keycode: Some(k), .. } => {
-                    if let Some(&(_, lane)) = key_to_lane.iter().find(|&&(key, _)| key == k) {
-                        if !held_keys.contains(&lane){
-                            pressed_keys.insert(lane);
-                            held_keys.insert(lane);
+
+                if let Some(row) = pause_menu {
+                    match input_event {
+                        InputEvent::Pressed(Action::Lane(2)) => pause_menu = Some((row + pause_options.len() - 1) % pause_options.len()),
+                        InputEvent::Pressed(Action::Lane(1)) => pause_menu = Some((row + 1) % pause_options.len()),
+                        InputEvent::Pressed(Action::Start) if row == 1 => return PlayOutcome::Restart,
+                        InputEvent::Pressed(Action::Start) if row == 2 => return PlayOutcome::Quit,
+                        InputEvent::Pressed(Action::Start | Action::Back) => {
+                            pause_menu = None;
+                            resume_at = Some(Instant::now() + RESUME_COUNTDOWN);
                         }
+                        _ => {}
                     }
+                    continue;
                 }
-                Event::KeyUp { keycode: Some(k), .. } => {
-                    if let Some(&(_, lane)) = key_to_lane.iter().find(|&&(key, _)| key == k) {
-                        pressed_keys.remove(&lane);
-                        held_keys.remove(&lane);
+
+                match input_event {
+                    InputEvent::Pressed(Action::Back) => {
+                        if !game_state.clock.is_paused() {
+                            game_state.clock.pause();
+                            Track::pause();
+                            if !playback {
+                                replay.pauses.push(elapsed_time);
+                            }
+                        }
+                        pause_menu = Some(0);
+                        resume_at = None;
                     }
+                    InputEvent::Pressed(Action::Lane(lane)) if !playback && !game_state.clock.is_paused() => {
+                        if let Some(hit) = judge.press(lane, event_time) {
+                            lane_hits[hit.lane] = Some(hit);
+                        }
+                        replay.record(event_time, lane, true);
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
         }
 
+        if let Some(at) = resume_at
+            && Instant::now() >= at {
+            resume_at = None;
+            game_state.clock.resume();
+            Track::resume();
+        }
+
+        if playback {
+            while let Some(event) = replay.events.get(next_replay_event).filter(|event| event.time <= elapsed_time) {
+                if event.pressed {
+                    if let Some(hit) = judge.press(event.lane, event.time) {
+                        lane_hits[hit.lane] = Some(hit);
+                    }
+                } else {
+                    judge.release(event.lane, event.time);
+                }
+                next_replay_event += 1;
+            }
+
+            // Replays pause where the player did, and count back in the same way
+            if replay.pauses.get(next_pause).is_some_and(|&time| time <= elapsed_time) && !game_state.clock.is_paused() {
+                next_pause += 1;
+                game_state.clock.pause();
+                Track::pause();
+                resume_at = Some(Instant::now() + RESUME_COUNTDOWN);
+            }
+        }
+        judge.update(elapsed_time);
+        judged_until = judged_until.max(elapsed_time);
+
         canvas.clear();
 
         // Draw BG
-        canvas.copy(&background_img, None, None).unwrap();
-        canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0,196));
-        canvas.fill_rect(Rect::new(175, 0, 400, 600)).unwrap();
+        canvas.copy(background_img, None, None).unwrap();
+        canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, theme.background_dim));
+        canvas.fill_rect(Rect::new(theme.playfield_x, 0, theme.playfield_width, display::HEIGHT)).unwrap();
 
 
         // Draw score
-        let surface = font
-        .render(&score.to_string())
-        .blended(sdl2::pixels::Color::RGB(255, 255, 255))
-        .map_err(|e| e.to_string()).unwrap();
-        let score_tex = texture_creator
-            .create_texture_from_surface(&surface)
-            .map_err(|e| e.to_string()).unwrap();
+        let score_tex = text_cache.texture(font, &judge.result.score.to_string(), theme.text);
 
         let TextureQuery { width, height, .. } = score_tex.query();
 
 
-        canvas.copy(&score_tex, None, Rect::new(15,15,width,height)).unwrap();
+        canvas.copy(score_tex, None, Rect::new(theme.score_position.0, theme.score_position.1, width, height)).unwrap();
 
+        let mode_text = match mode {
+            PlayMode::Live => "",
+            PlayMode::Replay => "REPLAY",
+            PlayMode::Autoplay => "AUTOPLAY",
+            PlayMode::Practice => "PRACTICE",
+        };
+        let (x, y) = theme.mode_position;
+        draw_text(canvas, text_cache, font, mode_text, theme.accent, x, y, 160);
+        let (x, y) = theme.modifiers_position;
+        draw_text(canvas, text_cache, font, &replay.modifiers, theme.text, x, y, 160);
 
-        // Draw combo
-        let surface = font
-        .render(&combo.to_string())
-        .blended(sdl2::pixels::Color::RGBA(255, 255, 255,128))
-        .map_err(|e| e.to_string()).unwrap();
-        let combo_tex = texture_creator
-            .create_texture_from_surface(&surface)
-            .map_err(|e| e.to_string()).unwrap();
+
+        // Draw combo, it pops a little every time it goes up
+        if judge.result.combo != combo_changed.0 {
+            combo_changed = (judge.result.combo, elapsed_time);
+        }
+        let pop = 1.0 + 0.3 * (1.0 - (elapsed_time - combo_changed.1) / COMBO_POP).clamp(0.0, 1.0);
+        let combo_tex = text_cache.texture(font, &judge.result.combo.to_string(), theme.combo_color);
 
         let TextureQuery { width, height, .. } = combo_tex.query();
+        let (width, height) = ((width as f64 * pop) as u32, (height as f64 * pop) as u32);
+        let combo_y = playfield.combo_y() as i32 + 20 - height as i32 / 2;
 
-        canvas.copy(&combo_tex, None, Rect::new(400-(width as i32)/2 -(note_gap/2),(judgment_line_y-100.0) as i32,width,height)).unwrap();        
+        canvas.copy(combo_tex, None, Rect::new(400-(width as i32)/2 -(note_gap/2),combo_y,width,height)).unwrap();        
 
 
 
 
-        // Draw controls
+        // Draw receptors
         for (i, &x) in [300-2*note_gap, 350-note_gap, 400, 450+note_gap].iter().enumerate() {
-            if held_keys.contains(&i) {
-                canvas.set_draw_color(sdl2::pixels::Color::RGB(200, 200, 255));
-            } else {
-                canvas.set_draw_color(sdl2::pixels::Color::RGB(100, 100, 100));
-            }
-            canvas.fill_rect(Rect::new(x, judgment_line_y as i32, 50, 25)).unwrap();
+            let glow = lane_hits[i].map_or(0.0, |hit| 1.0 - (elapsed_time - hit.time) / RECEPTOR_GLOW);
+            noteskin.draw_receptor(canvas, i, x, playfield.judgment_y(i) as i32, judge.is_held(i), glow.clamp(0.0, 1.0));
         }
 
-        let elapsed_time = game_state.start_time.elapsed().as_secs_f64();
-
 
         // Draw arrows
-        let mut taken_lanes = [false,false,false,false];
-        game_state.arrows.retain(|arrow| {
-            if elapsed_time < arrow.spawn_time {
-                return true
+        for note in judge.notes() {
+            let arrow = &note.arrow;
+            if elapsed_time < arrow.spawn_time || note.state == NoteState::Cleared {
+                continue;
             }
 
             let progress = (elapsed_time - arrow.spawn_time) / (arrow.hit_time - arrow.spawn_time);
-            let y_pos = spawn_y + progress * (judgment_line_y - spawn_y);
             let x_pos = 300 + (arrow.lane as i32)*50 + (arrow.lane as i32 -2) * note_gap;
+            let with_alpha = |color: Color, alpha: u8| Color::RGBA(color.r, color.g, color.b, alpha);
+            let missed = note.state == NoteState::Missed;
+            let head_color = if missed { Color::RGB(150, 150, 150) } else { noteskin::quantization_color(arrow.quantization) };
+
+            if arrow.mine {
+                let render = playfield.note(arrow.lane, progress);
+                if Playfield::on_screen(render.y) {
+                    noteskin.draw_mine(canvas, x_pos, render.y as i32, render.alpha);
+                }
+            } else if arrow.long {
+                // A held note stays on the receptor while its tail comes in
+                let head_progress = if note.state == NoteState::Holding { progress.min(1.0) } else { progress };
+                let tail_progress = progress - (arrow.end_time - arrow.hit_time) / (arrow.hit_time - arrow.spawn_time);
+                let head = playfield.note(arrow.lane, head_progress);
+                let tail = playfield.note(arrow.lane, tail_progress);
+                if tail_progress > 1.0 && !Playfield::on_screen(tail.y) {
+                    continue;
+                }
 
-            let mut long_pressed = false;
-
-            if arrow.long && held_keys.contains(&arrow.lane){
-                if elapsed_time >= arrow.hit_time && elapsed_time <= arrow.end_time {
-                    score += 5;
-                    long_pressed = true;
-                }                
-            } else if pressed_keys.contains(&arrow.lane) {
-                    let distance = (y_pos - judgment_line_y).abs();
-                    if distance < 50.0 && !taken_lanes[arrow.lane] {
-                        combo += 1;
-                        taken_lanes[arrow.lane] = true;
-                        score += match distance {
-                            d if d < 5.0 => 500, // Flawless
-                            d if d < 10.0 => 300,  // Perfect
-                            d if d < 20.0 => 200, // Good
-                            _ => 100,             // OK
-                        };
-                        return false;
-                    }
+                let body_color = if missed {
+                    Color::RGB(150, 150, 150)
+                } else if note.state == NoteState::Holding {
+                    Color::RGB(200, 200, 255)
+                } else if arrow.roll {
+                    Color::RGB(255, 190, 120)
+                } else {
+                    Color::RGB(255, 255, 255)
+                };
+
+                // The body is drawn in short pieces so fades follow it along its length
+                let pieces = ((head_progress - tail_progress) * 40.0).ceil().max(1.0) as usize;
+                let piece_progress = |i: usize| tail_progress + (head_progress - tail_progress) * i as f64 / pieces as f64;
+                for i in 0..pieces {
+                    let from = playfield.note(arrow.lane, piece_progress(i));
+                    let to = playfield.note(arrow.lane, piece_progress(i + 1));
+                    let top = from.y.min(to.y) + NOTE_HEIGHT / 2.0;
+                    let height = (from.y - to.y).abs().ceil().max(1.0);
+                    noteskin.draw_body(canvas, arrow.roll, x_pos, top as i32, height as u32, with_alpha(body_color, from.alpha));
+                }
+                noteskin.draw_cap(canvas, arrow.roll, x_pos, (tail.y + NOTE_HEIGHT / 2.0) as i32, with_alpha(body_color, tail.alpha));
+                noteskin.draw_tap(canvas, arrow.lane, x_pos, head.y as i32, with_alpha(head_color, head.alpha));
+            } else {
+                let render = playfield.note(arrow.lane, progress);
+                if Playfield::on_screen(render.y) {
+                    noteskin.draw_tap(canvas, arrow.lane, x_pos, render.y as i32, with_alpha(head_color, render.alpha));
+                }
             }
+        }
 
-            canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
+        // Explosions on hits and while holds are held
+        for (i, &x) in [300-2*note_gap, 350-note_gap, 400, 450+note_gap].iter().enumerate() {
+            let y = playfield.judgment_y(i) as i32;
+            if judge.is_holding(i) {
+                noteskin.draw_hold_explosion(canvas, i, x, y, (elapsed_time * 12.0).sin() * 0.5 + 0.5);
+            } else if let Some(hit) = lane_hits[i] {
+                let age = (elapsed_time - hit.time) / EXPLOSION_TIME;
+                if (0.0..1.0).contains(&age) {
+                    noteskin.draw_explosion(canvas, i, x, y, noteskin::judgment_color(hit.judgment), age);
+                }
+            }
+        }
 
-            if arrow.long {
-                let note_y = y_pos as i32;
-                let note_height = ((arrow.end_time - arrow.hit_time) / (arrow.hit_time - arrow.spawn_time)) * (judgment_line_y - spawn_y);
-            
-                let mut rect_height = note_height.abs() as u32; 
-                let rect_y = note_y - rect_height as i32;
+        // Judgment of the last note, shrinking in then fading
+        if let Some(hit) = judge.last_hit {
+            let age = (elapsed_time - hit.time) / JUDGMENT_TIME;
+            if (0.0..1.0).contains(&age) {
+                let color = noteskin::timing_color(hit.judgment, hit.offset);
+                let alpha = ((1.0 - age) * 4.0).min(1.0) * 255.0;
+                let scale = 1.0 + 0.25 * (1.0 - age * 8.0).max(0.0);
+                let center_x = 400 - note_gap / 2;
+                noteskin.draw_judgment(canvas, text_cache, font, hit.judgment, Color::RGBA(color.r, color.g, color.b, alpha as u8), center_x, playfield.judgment_text_y() as i32, scale);
+            }
+        }
 
-                if long_pressed {
-                    rect_height = (judgment_line_y - (rect_y as f64)) as u32;
-                    canvas.set_draw_color(sdl2::pixels::Color::RGB(200, 200, 255));
-                }
-            
-                canvas.fill_rect(Rect::new(x_pos + 10, rect_y, 30, rect_height)).unwrap();
-                canvas.fill_rect(Rect::new(x_pos+5, rect_y+(rect_height as i32), 40, 10)).unwrap();
+        if error_bar {
+            draw_error_bar(canvas, &judge.result.offsets, 400 - note_gap / 2, playfield.error_bar_y() as i32);
+        }
 
-                if long_pressed{
-                    rect_y < judgment_line_y  as i32 - 5
-                } else {
-                    rect_y < 600
-                }
-            } else{
-                canvas.fill_rect(Rect::new(x_pos, y_pos as i32, 50, 25)).unwrap();
+        // Countdown into a practice section
+        if game_state.section_end.is_some() && elapsed_time < game_state.music_start {
+            let count = (game_state.music_start - elapsed_time).ceil().to_string();
+            draw_text(canvas, text_cache, font, &count, theme.accent, 365 - note_gap / 2, 250, 100);
+        }
+
+        // Pause menu and the countdown back into play
+        if let Some(row) = pause_menu {
+            canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, 200));
+            canvas.fill_rect(Rect::new(200, 180, 400, 240)).unwrap();
+            draw_text(canvas, text_cache, font, "Paused", theme.text, 230, 195, 340);
+            for (i, option) in pause_options.iter().enumerate() {
+                let color = if i == row { theme.selected } else { theme.subtext };
+                draw_text(canvas, text_cache, font, option, color, 230, 260 + i as i32 * 50, 340);
+            }
+        } else if let Some(at) = resume_at {
+            let count = at.saturating_duration_since(Instant::now()).as_secs_f64().ceil().to_string();
+            draw_text(canvas, text_cache, font, &count, theme.accent, 365 - note_gap / 2, 250, 100);
+        }
 
+        let over = match game_state.section_end {
+            Some(end) => elapsed_time >= end,
+            None => music_started && !track.is_playing(),
+        };
+        if over && judge.finished() && !game_state.clock.is_paused() {
+            return PlayOutcome::Finished(judge.result);
+        }
+
+        pacer.draw_overlay(canvas, text_cache, small_font);
+        canvas.present();
+        pacer.wait();
+    }
+}
 
-                if y_pos >= 600.0{
-                    combo = 0;
-                    return false;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResultsChoice {
+    Retry,
+    WatchReplay,
+    SongSelect,
+}
+
+
+// Judgment breakdown after a play
+#[allow(clippy::too_many_arguments)]
+fn show_results(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+                event_pump: &mut sdl2::EventPump,
+                input: &mut Input,
+                text_cache: &mut TextCache,
+                pacer: &mut FramePacer,
+                font: &sdl2::ttf::Font,
+                small_font: &sdl2::ttf::Font,
+                theme: &Theme,
+                title: &str,
+                difficulty: &str,
+                background_img: &sdl2::render::Texture<'_>,
+                result: &PlayResult,
+                previous_best: Option<&PlayRecord>,
+               ) -> ResultsChoice {
+
+    let options = [
+        ("Retry", ResultsChoice::Retry),
+        ("Watch replay", ResultsChoice::WatchReplay),
+        ("Song select", ResultsChoice::SongSelect),
+    ];
+    let mut selected = 0;
+
+    let white = theme.text;
+    let grey = theme.subtext;
+    let gold = theme.accent;
+
+    let badge = if result.perfect_combo() {
+        "Perfect Full Combo"
+    } else if result.full_combo() {
+        "Full Combo"
+    } else {
+        ""
+    };
+    let best = match previous_best {
+        Some(best) if best.result.percentage() >= result.percentage() => {
+            format!("Personal best {} {:.2}% ({})", best.result.grade(), best.result.percentage(), best.date())
+        }
+        _ => "New personal best!".to_string(),
+    };
+
+    'results: loop {
+        for event in event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                std::process::exit(0);
+            }
+            display::handle_event(canvas, &event);
+            pacer.handle_event(&event);
+
+            for input_event in input.handle_event(&event) {
+                match input_event {
+                    InputEvent::Pressed(Action::Lane(2)) => selected = (selected + options.len() - 1) % options.len(),
+                    InputEvent::Pressed(Action::Lane(1)) => selected = (selected + 1) % options.len(),
+                    InputEvent::Pressed(Action::Start) => break 'results,
+                    InputEvent::Pressed(Action::Back) => return ResultsChoice::SongSelect,
+                    _ => {}
                 }
-                true
             }
-        });
+        }
+
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.copy(background_img, None, None).unwrap();
+        canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, theme.background_dim));
+        canvas.fill_rect(Rect::new(40, 20, 720, 560)).unwrap();
+
+        draw_text(canvas, text_cache, small_font, title, white, 60, 30, 680);
+        draw_text(canvas, text_cache, small_font, difficulty, grey, 60, 58, 680);
+
+        // Grade and score
+        draw_text(canvas, text_cache, font, result.grade(), gold, 60, 100, 200);
+        draw_text(canvas, text_cache, font, &format!("{:.2}%", result.percentage()), white, 60, 145, 300);
+        draw_text(canvas, text_cache, small_font, &format!("Score {}", result.score), grey, 60, 190, 300);
+        draw_text(canvas, text_cache, small_font, badge, gold, 60, 220, 300);
+        draw_text(canvas, text_cache, small_font, &best, white, 60, 260, 340);
+
+        // Judgment breakdown
+        let mut lines: Vec<(String, u32)> = Judgment::ALL.iter()
+            .map(|&judgment| (judgment.name().to_string(), result.count(judgment)))
+            .collect();
+        lines.extend([
+            ("Max combo".to_string(), result.max_combo),
+            ("Hold OK".to_string(), result.holds_ok),
+            ("Hold NG".to_string(), result.holds_ng),
+            ("Mines hit".to_string(), result.mines_hit),
+        ]);
+        for (i, (name, count)) in lines.iter().enumerate() {
+            let y = 100 + i as i32 * 34;
+            draw_text(canvas, text_cache, small_font, name, grey, 420, y, 200);
+            draw_text(canvas, text_cache, small_font, &count.to_string(), white, 640, y, 100);
+        }
+
+        // How early or late the hits were
+        if !result.offsets.is_empty() {
+            draw_offset_histogram(canvas, &result.offsets, 420, 400);
+            let mean = result.mean_offset().unwrap_or(0.0) * 1000.0;
+            let deviation = result.offset_deviation().unwrap_or(0.0) * 1000.0;
+            draw_text(canvas, text_cache, small_font, &format!("Mean {:+.1} ms   SD {:.1} ms", mean, deviation), grey, 420, 500, 320);
+            if let Some(suggested) = result.suggested_offset() {
+                let advice = format!("Suggested offset change: {:+.0} ms", suggested * 1000.0);
+                draw_text(canvas, text_cache, small_font, &advice, white, 420, 530, 320);
+            }
+        }
 
-        if !Music::is_playing() && game_state.arrows.is_empty(){
-            break 'running;
+        // Retry / replay / song select
+        for (i, (option, _)) in options.iter().enumerate() {
+            let rect = Rect::new(60, 400 + i as i32 * 60, 300, 50);
+            if i == selected {
+                canvas.set_draw_color(theme.selected);
+            } else {
+                canvas.set_draw_color(theme.unselected);
+            }
+            canvas.fill_rect(rect).unwrap();
+            draw_text(canvas, text_cache, small_font, option, white, rect.x + 20, rect.y + 12, 260);
         }
 
+        pacer.draw_overlay(canvas, text_cache, small_font);
         canvas.present();
-        std::thread::sleep(Duration::from_millis(16));
+        pacer.wait();
     }
 
+    options[selected].1
 }
 
 
+enum DifficultyChoice {
+    Play(u32),
+    // Watch the last replay of the difficulty
+    Watch(u32, Replay),
+    Practice(u32),
+}
+
+
+#[allow(clippy::too_many_arguments)]
 fn choose_difficulty(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, 
                      event_pump: &mut sdl2::EventPump,
+                     input: &mut Input,
+                     text_cache: &mut TextCache,
+                     pacer: &mut FramePacer,
                      font: &sdl2::ttf::Font, 
+                     small_font: &sdl2::ttf::Font,
+                     theme: &Theme,
                      game_state: &GameState,
                      background_img: &sdl2::render::Texture<'_>,
-                     music: &Music<'static>
-                    ) -> u32 {
-
-    let texture_creator = canvas.texture_creator();
-    let mut selected: u32 = 0;
-    let difficulties = game_state.difficulties.clone();
-
-    // Text surface and texture
-    let surface = font
-    .render("Choose a difficulty")
-    .blended(sdl2::pixels::Color::RGBA(255, 255, 255,0))
-    .map_err(|e| e.to_string()).unwrap();
-    let intro_tex = texture_creator
-        .create_texture_from_surface(&surface)
-        .map_err(|e| e.to_string()).unwrap();
-
-    let TextureQuery { width, height, .. } = intro_tex.query();
-
+                     music: Option<&Music<'static>>,
+                     replays_dir: &Path,
+                     modifiers: &mut Modifiers,
+                    ) -> Option<DifficultyChoice> {
+
+    // Charts for other pads than the four panel one are left off the list
+    let charts: Vec<u32> = (0..game_state.difficulties.len() as u32).filter(|&chart| game_state.playable(chart)).collect();
+    if charts.is_empty() {
+        eprintln!("{} has no charts for four panels", game_state.title);
+        return None;
+    }
+    let mut selected: usize = 0;
 
 
-    music.play(-1).expect("Failed to play music");
-    sdl2::mixer::Music::set_pos(game_state.sample_start).unwrap();
+    if let Some(music) = music {
+        music.play(-1).expect("Failed to play music");
+        sdl2::mixer::Music::set_pos(game_state.sample_start).unwrap();
+    }
 
     let mut last_seek = Instant::now();
+    // Selected row while the modifier panel is open
+    let mut modifier_row: Option<usize> = None;
 
-    'menu: loop {
-        if last_seek.elapsed() >= Duration::from_secs_f64(game_state.sample_len){
+    let choice = 'menu: loop {
+        if music.is_some() && last_seek.elapsed() >= Duration::from_secs_f64(game_state.sample_len){
             sdl2::mixer::Music::set_pos(game_state.sample_start).unwrap();
             last_seek = Instant::now();
         }
 
         for event in event_pump.poll_iter() {
-            match event {
-                sdl2::event::Event::Quit { .. } => std::process::exit(0),
-                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::Up), .. } => {
-                    if selected > 0 {
-                        selected -= 1;
+            if let sdl2::event::Event::Quit { .. } = event {
+                std::process::exit(0);
+            }
+            display::handle_event(canvas, &event);
+            pacer.handle_event(&event);
+            if let Event::KeyDown { keycode: Some(Keycode::F2), .. } = event {
+                modifier_row = if modifier_row.is_none() { Some(0) } else { None };
+                continue;
+            }
+
+            // Pad arrows double as menu directions: lane 2 is up and lane 1 is down
+            for input_event in input.handle_event(&event) {
+                if let Some(row) = modifier_row {
+                    match input_event {
+                        InputEvent::Pressed(Action::Lane(2)) => modifier_row = Some((row + Modifiers::ROWS - 1) % Modifiers::ROWS),
+                        InputEvent::Pressed(Action::Lane(1)) => modifier_row = Some((row + 1) % Modifiers::ROWS),
+                        InputEvent::Pressed(Action::Lane(lane)) => modifiers.adjust(row, if lane == 0 { -1.0 } else { 1.0 }),
+                        InputEvent::Pressed(Action::Start | Action::Back) => modifier_row = None,
+                        _ => {}
                     }
+                    continue;
                 }
-                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::Down), .. } => {
-                    if selected < (difficulties.len() - 1).try_into().unwrap() {
+
+                match input_event {
+                    InputEvent::Pressed(Action::Lane(2)) => {
+                        selected = selected.saturating_sub(1);
+                    }
+                    InputEvent::Pressed(Action::Lane(1)) if selected + 1 < charts.len() => {
                         selected += 1;
                     }
+                    InputEvent::Pressed(Action::Start) => {
+                        break 'menu DifficultyChoice::Play(charts[selected]);
+                    }
+                    // Lane 0 (left) practices the chart, lane 3 (right) watches its last replay if there is one
+                    InputEvent::Pressed(Action::Lane(0)) => {
+                        break 'menu DifficultyChoice::Practice(charts[selected]);
+                    }
+                    InputEvent::Pressed(Action::Lane(3)) => {
+                        let chart = game_state.chart_hashes.get(charts[selected] as usize).copied().unwrap_or(0);
+                        if let Some(replay) = Replay::latest(replays_dir, chart) {
+                            break 'menu DifficultyChoice::Watch(charts[selected], replay);
+                        }
+                    }
+                    InputEvent::Pressed(Action::Back) => {
+                        sdl2::mixer::Music::halt();
+                        return None;
+                    }
+                    _ => {}
                 }
-                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::Return), .. } => {
-                    break 'menu;
-                }
-                _ => {}
             }
         }
         // Draw start
         canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
         canvas.clear();
-        canvas.copy(&background_img, None, None).unwrap();
+        canvas.copy(background_img, None, None).unwrap();
 
         // Draw text
+        let intro_tex = text_cache.texture(font, "Choose a difficulty", theme.text);
+        let TextureQuery { width, height, .. } = intro_tex.query();
         canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, 128));
-        let rect = Rect::new(400-(width as i32 + 50)/2, 50, width+50, height+10);
+        let rect = Rect::new(400-(width as i32 + 50)/2, theme.difficulty_title_y, width+50, height+10);
         canvas.fill_rect(rect).unwrap();
 
-        canvas.copy(&intro_tex, None, Rect::new(400 - (width as i32)/2, 50,width,height)).unwrap();        
+        canvas.copy(intro_tex, None, Rect::new(400 - (width as i32)/2, theme.difficulty_title_y,width,height)).unwrap();        
 
 
 
-        for (i, diff) in difficulties.iter().enumerate() {
-            let rect_y = 150 + (i as i32 * 80);
+        for (i, &chart) in charts.iter().enumerate() {
+            let diff = &game_state.difficulties[chart as usize];
+            let (x, y) = theme.difficulty_position;
+            let rect_y = y + i as i32 * theme.difficulty_spacing;
 
             // highlight selected
-            if i == selected as usize {
-                canvas.set_draw_color(sdl2::pixels::Color::RGB(200, 200, 255));
+            if i == selected {
+                canvas.set_draw_color(theme.selected);
             } else {
-                canvas.set_draw_color(sdl2::pixels::Color::RGB(100, 100, 100));
+                canvas.set_draw_color(theme.unselected);
             }
-            let rect = Rect::new(250, rect_y, 300, 60);
+            let rect = Rect::new(x, rect_y, theme.difficulty_size.0, theme.difficulty_size.1);
             canvas.fill_rect(rect).unwrap();
 
-            let surface = font
-                .render(diff)
-                .blended(sdl2::pixels::Color::RGB(255, 255, 255))
-                .unwrap();
-            let texture = texture_creator.create_texture_from_surface(&surface).unwrap();
+            let texture = text_cache.texture(font, diff, theme.text);
             let TextureQuery { width, height, .. } = texture.query();
 
             let text_x = rect.x + (rect.width() as i32 - width as i32) / 2;
             let text_y = rect.y + (rect.height() as i32 - height as i32) / 2;
 
-            canvas.copy(&texture, None, Some(Rect::new(text_x, text_y, width, height))).unwrap();
+            canvas.copy(texture, None, Some(Rect::new(text_x, text_y, width, height))).unwrap();
 
 
         }
 
+        let grey = theme.subtext;
+        draw_text(canvas, text_cache, small_font, &modifiers.to_tag(), grey, 20, 530, 760);
+        draw_text(canvas, text_cache, small_font, "Left: practice   Right: watch replay   F2: modifiers", grey, 20, 562, 760);
+
+        if let Some(row) = modifier_row {
+            draw_panel(canvas, text_cache, small_font, theme, "Modifiers (left/right to change)", &modifiers.lines(), row);
+        }
+
+        pacer.draw_overlay(canvas, text_cache, small_font);
         canvas.present();
-        std::thread::sleep(std::time::Duration::from_millis(16));
-    }
+        pacer.wait();
+    };
 
     sdl2::mixer::Music::halt();
 
-    selected
-}
\ No newline at end of file
+    Some(choice)
+}
+
+
+// Picks the section and rate to practice
+#[allow(clippy::too_many_arguments)]
+fn choose_section(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+                  event_pump: &mut sdl2::EventPump,
+                  input: &mut Input,
+                  text_cache: &mut TextCache,
+                  pacer: &mut FramePacer,
+                  font: &sdl2::ttf::Font,
+                  small_font: &sdl2::ttf::Font,
+                  theme: &Theme,
+                  game_state: &GameState,
+                  difficulty: u32,
+                  background_img: &sdl2::render::Texture<'_>,
+                  mut practice: Practice,
+                 ) -> Option<Practice> {
+
+    let sections = Practice::sections(&game_state.labels);
+    let measures = game_state.measure_counts.get(difficulty as usize).copied().unwrap_or(0);
+    let mut row = 0;
+
+    let white = theme.text;
+    let grey = theme.subtext;
+
+    loop {
+        for event in event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                std::process::exit(0);
+            }
+            display::handle_event(canvas, &event);
+            pacer.handle_event(&event);
+
+            for input_event in input.handle_event(&event) {
+                match input_event {
+                    InputEvent::Pressed(Action::Lane(2)) => row = (row + Practice::ROWS - 1) % Practice::ROWS,
+                    InputEvent::Pressed(Action::Lane(1)) => row = (row + 1) % Practice::ROWS,
+                    InputEvent::Pressed(Action::Lane(lane)) => {
+                        practice.adjust(row, if lane == 0 { -1 } else { 1 }, &sections, measures);
+                    }
+                    InputEvent::Pressed(Action::Start) => return Some(practice),
+                    InputEvent::Pressed(Action::Back) => return None,
+                    _ => {}
+                }
+            }
+        }
+
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.copy(background_img, None, None).unwrap();
+        canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, theme.background_dim));
+        // Rows are packed tighter than the menus, with every modifier listed they'd run off the screen
+        canvas.fill_rect(Rect::new(150, 80, 500, 120 + Practice::ROWS as u32 * 26)).unwrap();
+
+        draw_text(canvas, text_cache, font, "Practice", white, 170, 90, 460);
+        let difficulty_name = game_state.difficulties.get(difficulty as usize).map(String::as_str).unwrap_or_default();
+        draw_text(canvas, text_cache, small_font, &format!("{} ({} measures)", difficulty_name, measures), grey, 170, 140, 460);
+
+        for (i, line) in practice.lines(&sections, measures).iter().enumerate() {
+            let color = if i == row { theme.selected } else { grey };
+            draw_text(canvas, text_cache, small_font, line, color, 170, 175 + i as i32 * 26, 460);
+        }
+
+        pacer.draw_overlay(canvas, text_cache, small_font);
+        canvas.present();
+        pacer.wait();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn choose_profile(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+                  event_pump: &mut sdl2::EventPump,
+                  input: &mut Input,
+                  text_cache: &mut TextCache,
+                  pacer: &mut FramePacer,
+                  font: &sdl2::ttf::Font,
+                  small_font: &sdl2::ttf::Font,
+                  theme: &Theme,
+                 ) -> Option<Profile> {
+
+    let text_input = canvas.window().subsystem().text_input();
+    text_input.stop();
+
+    let mut profiles = Profile::list();
+    let mut selected = 0;
+    // Some while typing the name of a new profile
+    let mut new_name: Option<String> = None;
+
+    let white = theme.text;
+    let grey = theme.subtext;
+
+    loop {
+        for event in event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                std::process::exit(0);
+            }
+            display::handle_event(canvas, &event);
+            pacer.handle_event(&event);
+
+            if let Some(name) = &mut new_name {
+                match event {
+                    Event::TextInput { ref text, .. } => name.push_str(text),
+                    Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                        name.pop();
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
+                        if let Some(profile) = Profile::create(name) {
+                            profiles = Profile::list();
+                            selected = profiles.iter().position(|p| p.dir == profile.dir).unwrap_or(0);
+                        }
+                        new_name = None;
+                        text_input.stop();
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                        new_name = None;
+                        text_input.stop();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            // The last row is "New profile"
+            for input_event in input.handle_event(&event) {
+                match input_event {
+                    InputEvent::Pressed(Action::Lane(2)) => selected = selected.saturating_sub(1),
+                    InputEvent::Pressed(Action::Lane(1)) => selected = (selected + 1).min(profiles.len()),
+                    InputEvent::Pressed(Action::Start) if selected < profiles.len() => {
+                        return Some(profiles.swap_remove(selected));
+                    }
+                    InputEvent::Pressed(Action::Start) => {
+                        new_name = Some(String::new());
+                        text_input.start();
+                    }
+                    InputEvent::Pressed(Action::Back) => return None,
+                    _ => {}
+                }
+            }
+        }
+
+        canvas.set_draw_color(theme.menu_background);
+        canvas.clear();
+
+        draw_text(canvas, text_cache, font, "Choose a profile", white, 250, 40, 400);
+
+        for i in 0..=profiles.len() {
+            let rect = Rect::new(200, 120 + i as i32 * 70, 400, 60);
+            if i == selected {
+                canvas.set_draw_color(theme.selected);
+            } else {
+                canvas.set_draw_color(theme.unselected);
+            }
+            canvas.fill_rect(rect).unwrap();
+
+            match profiles.get(i) {
+                Some(profile) => {
+                    draw_text(canvas, text_cache, small_font, &profile.name, white, rect.x + 15, rect.y + 4, 370);
+                    draw_text(canvas, text_cache, small_font, &profile.stats_string(), grey, rect.x + 15, rect.y + 30, 370);
+                }
+                None => {
+                    let label = match &new_name {
+                        Some(name) => format!("Name: {}_", name),
+                        None => "New profile".to_string(),
+                    };
+                    draw_text(canvas, text_cache, small_font, &label, white, rect.x + 15, rect.y + 16, 370);
+                }
+            }
+        }
+
+        pacer.draw_overlay(canvas, text_cache, small_font);
+        canvas.present();
+        pacer.wait();
+    }
+}
+
+
+// Song wheel state that survives going into a song and back
+struct WheelState {
+    selected: usize, // index into the library
+    filter: SongFilter,
+    sort: SortOrder,
+    // The profile's preferences, F4 and F5 switch them
+    translit: bool,
+    error_bar: bool,
+}
+
+
+#[derive(PartialEq)]
+enum WheelMode {
+    Browse,
+    Search,
+    Filters(usize), // selected filter row
+    Modifiers(usize), // selected modifier row
+}
+
+
+#[allow(clippy::too_many_arguments)]
+fn choose_song(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+               event_pump: &mut sdl2::EventPump,
+               input: &mut Input,
+               text_cache: &mut TextCache,
+               pacer: &mut FramePacer,
+               font: &sdl2::ttf::Font,
+               small_font: &sdl2::ttf::Font,
+               theme: &Theme,
+               library: &Library,
+               scores: &ScoreDb,
+               wheel: &mut WheelState,
+               modifiers: &mut Modifiers,
+              ) -> Option<usize> {
+
+    let texture_creator = canvas.texture_creator();
+    let text_input = canvas.window().subsystem().text_input();
+    text_input.stop();
+
+    let songs = &library.songs;
+    let steps_types = library.steps_types();
+
+    let row_height = 44;
+    let visible_rows: i32 = 6; // on each side of the selected one
+    let filter_rows = 7;
+
+    let mut mode = WheelMode::Browse;
+    let mut visible = library.select(&wheel.filter, wheel.sort, scores, wheel.translit);
+    let mut selected = visible.iter().position(|&i| i == wheel.selected).unwrap_or(0);
+
+    let mut banner = None;
+    let mut cdtitle = None;
+    let mut preview: Option<Music<'static>> = None;
+    let mut preview_loaded = false;
+    let mut selection_changed = true;
+    let mut filter_changed = false;
+    let mut last_change = Instant::now();
+    let mut last_seek = Instant::now();
+
+    'wheel: loop {
+        if filter_changed {
+            filter_changed = false;
+            let current = visible.get(selected).copied();
+            visible = library.select(&wheel.filter, wheel.sort, scores, wheel.translit);
+            selected = visible.iter().position(|&i| Some(i) == current).unwrap_or(0);
+            selection_changed = visible.get(selected).copied() != current;
+        }
+
+        if selection_changed {
+            selection_changed = false;
+            last_change = Instant::now();
+            Music::halt();
+            preview = None;
+            preview_loaded = false;
+
+            let song = visible.get(selected).map(|&i| &songs[i]);
+            let load = |path: PathBuf| texture_creator.load_texture(path).ok();
+            banner = song.and_then(|song| assets::find_banner(&song.dir, &song.banner)).and_then(load);
+            cdtitle = song.filter(|song| !song.cdtitle.is_empty()).and_then(|song| load(song.dir.join(&song.cdtitle)));
+        }
+
+        // Wait a moment before loading the preview so scrolling through the wheel stays snappy
+        let song = visible.get(selected).map(|&i| &songs[i]);
+        if let Some(song) = song {
+            if !preview_loaded && last_change.elapsed() >= Duration::from_millis(400) {
+                preview_loaded = true;
+                preview = assets::find_music(&song.dir, &song.music).and_then(|path| Music::from_file(path).ok());
+                if preview.as_ref().is_some_and(|music| music.play(-1).is_ok()) {
+                    Music::set_pos(song.sample_start).ok();
+                    last_seek = Instant::now();
+                }
+            }
+            if preview.is_some() && last_seek.elapsed() >= Duration::from_secs_f64(song.sample_len) {
+                Music::set_pos(song.sample_start).ok();
+                last_seek = Instant::now();
+            }
+        }
+
+
+        for event in event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                std::process::exit(0);
+            }
+            display::handle_event(canvas, &event);
+            pacer.handle_event(&event);
+
+            // Typing goes to the search box, so the lane keys can't move the wheel meanwhile
+            if mode == WheelMode::Search {
+                match event {
+                    Event::TextInput { ref text, .. } => {
+                        wheel.filter.query.push_str(text);
+                        filter_changed = true;
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                        wheel.filter.query.pop();
+                        filter_changed = true;
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Return | Keycode::Escape), .. } => {
+                        mode = WheelMode::Browse;
+                        text_input.stop();
+                    }
+                    Event::KeyUp { .. } => {
+                        input.handle_event(&event);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match event {
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
+                    wheel.sort = wheel.sort.next();
+                    filter_changed = true;
+                    continue;
+                }
+                Event::KeyDown { keycode: Some(Keycode::Slash), .. } => {
+                    mode = WheelMode::Search;
+                    text_input.start();
+                    continue;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F1), .. } => {
+                    mode = if mode == WheelMode::Browse { WheelMode::Filters(0) } else { WheelMode::Browse };
+                    continue;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F2), .. } => {
+                    mode = if mode == WheelMode::Browse { WheelMode::Modifiers(0) } else { WheelMode::Browse };
+                    continue;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F4), .. } => {
+                    wheel.translit = !wheel.translit;
+                    filter_changed = true;
+                    continue;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                    wheel.error_bar = !wheel.error_bar;
+                    continue;
+                }
+                _ => {}
+            }
+
+            for input_event in input.handle_event(&event) {
+                let InputEvent::Pressed(action) = input_event else {
+                    continue;
+                };
+
+                if let WheelMode::Filters(row) = mode {
+                    match action {
+                        Action::Lane(2) => mode = WheelMode::Filters((row + filter_rows - 1) % filter_rows),
+                        Action::Lane(1) => mode = WheelMode::Filters((row + 1) % filter_rows),
+                        Action::Lane(lane) => {
+                            let delta = if lane == 0 { -1.0 } else { 1.0 };
+                            adjust_filter(&mut wheel.filter, row, delta, &steps_types);
+                            filter_changed = true;
+                        }
+                        Action::Start | Action::Back => mode = WheelMode::Browse,
+                    }
+                    continue;
+                }
+
+                if let WheelMode::Modifiers(row) = mode {
+                    match action {
+                        Action::Lane(2) => mode = WheelMode::Modifiers((row + Modifiers::ROWS - 1) % Modifiers::ROWS),
+                        Action::Lane(1) => mode = WheelMode::Modifiers((row + 1) % Modifiers::ROWS),
+                        Action::Lane(lane) => modifiers.adjust(row, if lane == 0 { -1.0 } else { 1.0 }),
+                        Action::Start | Action::Back => mode = WheelMode::Browse,
+                    }
+                    continue;
+                }
+
+                // Up/down scroll the wheel, left/right jump to the previous/next group
+                let count = visible.len().max(1);
+                let group_of = |position: usize| visible.get(position).map(|&i| &songs[i].group);
+                match action {
+                    Action::Lane(2) => {
+                        selected = (selected + count - 1) % count;
+                        selection_changed = true;
+                    }
+                    Action::Lane(1) => {
+                        selected = (selected + 1) % count;
+                        selection_changed = true;
+                    }
+                    Action::Lane(0) => {
+                        let group = group_of(selected);
+                        let group_start = (0..count).find(|&p| group_of(p) == group).unwrap_or(0);
+                        let previous_group = group_of((group_start + count - 1) % count);
+                        selected = (0..count).find(|&p| group_of(p) == previous_group).unwrap_or(0);
+                        selection_changed = true;
+                    }
+                    Action::Lane(_) => {
+                        let group = group_of(selected);
+                        selected = (selected..count).find(|&p| group_of(p) != group).unwrap_or(0);
+                        selection_changed = true;
+                    }
+                    Action::Start if !visible.is_empty() => break 'wheel,
+                    Action::Start => {}
+                    Action::Back => {
+                        Music::halt();
+                        return None;
+                    }
+                }
+            }
+        }
+
+
+        canvas.set_draw_color(theme.menu_background);
+        canvas.clear();
+
+        let white = theme.text;
+        let grey = theme.subtext;
+
+        if let Some(song) = visible.get(selected).map(|&i| &songs[i]) {
+            // Banner
+            let banner_rect = Rect::new(20, 20, 360, 112);
+            match &banner {
+                Some(banner) => canvas.copy(banner, None, banner_rect).unwrap(),
+                None => {
+                    canvas.set_draw_color(theme.list_item);
+                    canvas.fill_rect(banner_rect).unwrap();
+                    draw_text(canvas, text_cache, small_font, &song.group, grey, 30, 30, 340);
+                }
+            }
+
+            // CDTitle in the banner's corner, shrunk to fit and keeping its shape
+            if let Some(cdtitle) = &cdtitle {
+                let query = cdtitle.query();
+                let scale = (100.0 / query.width as f64).min(60.0 / query.height as f64).min(1.0);
+                let (width, height) = ((query.width as f64 * scale) as u32, (query.height as f64 * scale) as u32);
+                canvas.copy(cdtitle, None, Rect::new(375 - width as i32, 127 - height as i32, width, height)).unwrap();
+            }
+
+            // Song info
+            let translit = wheel.translit;
+            draw_text(canvas, text_cache, font, song.display_title(translit), white, 20, 135, 360);
+            draw_text(canvas, text_cache, small_font, song.display_subtitle(translit), grey, 20, 177, 360);
+            draw_text(canvas, text_cache, small_font, song.display_artist(translit), white, 20, 203, 360);
+            draw_text(canvas, text_cache, small_font, &song.group, grey, 20, 229, 170);
+            draw_text(canvas, text_cache, small_font, &song.genre, grey, 200, 229, 180);
+            draw_text(canvas, text_cache, small_font, &song.bpm_string(), white, 20, 255, 170);
+            if !song.credit.is_empty() {
+                draw_text(canvas, text_cache, small_font, &format!("by {}", song.credit), grey, 200, 255, 180);
+            }
+
+            for (i, diff) in song.difficulties.iter().enumerate() {
+                let meter = song.meters.get(i).copied().unwrap_or(0);
+                let y = 310 + (i as i32) * 30;
+                draw_text(canvas, text_cache, small_font, diff, white, 20, y, 170);
+                draw_text(canvas, text_cache, small_font, &meter.to_string(), white, 200, y, 40);
+
+                if let Some(best) = song.chart_hashes.get(i).and_then(|&chart| scores.best(chart, modifiers)) {
+                    let best = format!("{} {:.2}%", best.result.grade(), best.result.percentage());
+                    draw_text(canvas, text_cache, small_font, &best, grey, 250, y, 130);
+                }
+            }
+        } else {
+            draw_text(canvas, text_cache, font, "No songs match", white, 20, 145, 360);
+        }
+
+        // Wheel
+        let center_y = 300 - row_height / 2;
+        for offset in -visible_rows..=visible_rows {
+            // Don't wrap around into songs that are already on screen
+            if (visible.len() as i32) < offset.abs() * 2 + 1 {
+                continue;
+            }
+            let index = visible[(selected as i32 + offset).rem_euclid(visible.len() as i32) as usize];
+            let rect = Rect::new(420, center_y + offset * row_height, 360, (row_height - 4) as u32);
+
+            if offset == 0 {
+                canvas.set_draw_color(theme.selected);
+            } else {
+                canvas.set_draw_color(theme.list_item);
+            }
+            canvas.fill_rect(rect).unwrap();
+
+            let color = if offset == 0 { theme.menu_background } else { white };
+            draw_text(canvas, text_cache, small_font, songs[index].display_title(wheel.translit), color, rect.x + 10, rect.y + 8, 340);
+        }
+
+        // Search and sort bar
+        canvas.set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, 200));
+        canvas.fill_rect(Rect::new(0, 564, 800, 36)).unwrap();
+        let search = if mode == WheelMode::Search {
+            format!("Search: {}_", wheel.filter.query)
+        } else if wheel.filter.query.is_empty() {
+            "/: search  Tab: sort  F1: filters  F2: modifiers  F4: translit".to_string()
+        } else {
+            format!("Search: {}", wheel.filter.query)
+        };
+        draw_text(canvas, text_cache, small_font, &search, white, 20, 568, 480);
+        let sort = format!("Sort: {}  ({} songs)", wheel.sort.name(), visible.len());
+        draw_text(canvas, text_cache, small_font, &sort, grey, 520, 568, 270);
+
+        // Filter and modifier panels
+        if let WheelMode::Filters(row) = mode {
+            draw_panel(canvas, text_cache, small_font, theme, "Filters (left/right to change)", &filter_lines(&wheel.filter), row);
+        }
+        if let WheelMode::Modifiers(row) = mode {
+            draw_panel(canvas, text_cache, small_font, theme, "Modifiers (left/right to change)", &modifiers.lines(), row);
+        }
+
+        pacer.draw_overlay(canvas, text_cache, small_font);
+        canvas.present();
+        pacer.wait();
+    }
+
+    text_input.stop();
+    Music::halt();
+
+    wheel.selected = visible[selected];
+    Some(wheel.selected)
+}
+
+
+fn filter_lines(filter: &SongFilter) -> Vec<String> {
+    let any = |value: Option<String>| value.unwrap_or_else(|| "Any".to_string());
+    let length = |l: f64| format!("{}:{:02}", l as u32 / 60, l as u32 % 60);
+    vec![
+        format!("Steps type: {}", any(filter.steps_type.clone())),
+        format!("Min meter: {}", any(filter.min_meter.map(|m| m.to_string()))),
+        format!("Max meter: {}", any(filter.max_meter.map(|m| m.to_string()))),
+        format!("Min BPM: {}", any(filter.min_bpm.map(|b| format!("{:.0}", b)))),
+        format!("Max BPM: {}", any(filter.max_bpm.map(|b| format!("{:.0}", b)))),
+        format!("Min length: {}", any(filter.min_length.map(length))),
+        format!("Max length: {}", any(filter.max_length.map(length))),
+    ]
+}
+
+
+// Steps a filter row up or down. Going below the first value turns the filter off.
+fn adjust_filter(filter: &mut SongFilter, row: usize, delta: f64, steps_types: &[String]) {
+    let step = |value: Option<f64>, size: f64, first: f64| -> Option<f64> {
+        match value {
+            None if delta > 0.0 => Some(first),
+            None => None,
+            Some(v) if v + delta * size < first => None,
+            Some(v) => Some(v + delta * size),
+        }
+    };
+
+    match row {
+        0 => {
+            let position = filter.steps_type.as_ref().and_then(|t| steps_types.iter().position(|s| s == t));
+            let next = match position {
+                None if delta > 0.0 => steps_types.first(),
+                None => steps_types.last(),
+                Some(p) if delta > 0.0 => steps_types.get(p + 1),
+                Some(p) => p.checked_sub(1).and_then(|p| steps_types.get(p)),
+            };
+            filter.steps_type = next.cloned();
+        }
+        1 => filter.min_meter = step(filter.min_meter.map(|m| m as f64), 1.0, 1.0).map(|m| m as u32),
+        2 => filter.max_meter = step(filter.max_meter.map(|m| m as f64), 1.0, 1.0).map(|m| m as u32),
+        3 => filter.min_bpm = step(filter.min_bpm, 10.0, 60.0),
+        4 => filter.max_bpm = step(filter.max_bpm, 10.0, 60.0),
+        5 => filter.min_length = step(filter.min_length, 30.0, 60.0),
+        _ => filter.max_length = step(filter.max_length, 30.0, 60.0),
+    }
+}
+
+
+// Box of option lines over the middle of the screen, with the selected row highlighted
+fn draw_panel(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+              text_cache: &mut TextCache,
+              font: &sdl2::ttf::Font,
+              theme: &Theme,
+              title: &str,
+              lines: &[String],
+              row: usize,
+             ) {
+    let white = theme.text;
+    let grey = theme.subtext;
+
+    canvas.set_draw_color(theme.panel);
+    canvas.fill_rect(Rect::new(150, 60, 500, 60 + lines.len() as u32 * 40)).unwrap();
+    draw_text(canvas, text_cache, font, title, white, 170, 70, 460);
+
+    for (i, line) in lines.iter().enumerate() {
+        let color = if i == row { theme.selected } else { grey };
+        draw_text(canvas, text_cache, font, line, color, 170, 110 + i as i32 * 40, 460);
+    }
+}
+
+
+// Recent hit offsets as ticks over the timing windows, 1 px to a millisecond, newest brightest
+fn draw_error_bar(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, offsets: &[f64], center_x: i32, y: i32) {
+    let windows = [
+        (Judgment::Ok, judge::OK_WINDOW),
+        (Judgment::Good, judge::GOOD_WINDOW),
+        (Judgment::Perfect, judge::PERFECT_WINDOW),
+        (Judgment::Flawless, judge::FLAWLESS_WINDOW),
+    ];
+    for (judgment, window) in windows {
+        let color = noteskin::judgment_color(judgment);
+        let half = (window * 1000.0) as i32;
+        canvas.set_draw_color(Color::RGBA(color.r, color.g, color.b, 90));
+        canvas.fill_rect(Rect::new(center_x - half, y, 2 * half as u32, 6)).unwrap();
+    }
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.fill_rect(Rect::new(center_x - 1, y - 6, 2, 18)).unwrap();
+
+    let recent = &offsets[offsets.len().saturating_sub(ERROR_BAR_HITS)..];
+    for (i, offset) in recent.iter().enumerate() {
+        let alpha = 255 * (i + 1) / recent.len();
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha as u8));
+        canvas.fill_rect(Rect::new(center_x + (offset * 1000.0) as i32 - 1, y - 4, 2, 14)).unwrap();
+    }
+}
+
+
+// Hits per 10 ms of offset across the OK window, in a 297x90 box at (x, y)
+fn draw_offset_histogram(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, offsets: &[f64], x: i32, y: i32) {
+    const BINS: usize = 27;
+    const BIN_WIDTH: f64 = 2.0 * judge::OK_WINDOW / BINS as f64;
+    let mut counts = [0u32; BINS];
+    for offset in offsets {
+        let bin = ((offset + judge::OK_WINDOW) / BIN_WIDTH) as usize;
+        counts[bin.min(BINS - 1)] += 1;
+    }
+    let most = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    canvas.set_draw_color(Color::RGB(100, 100, 100));
+    canvas.fill_rect(Rect::new(x, y + 90, 297, 1)).unwrap();
+    canvas.fill_rect(Rect::new(x + 148, y, 1, 90)).unwrap();
+    for (i, &count) in counts.iter().enumerate() {
+        let center = ((i as f64 + 0.5) * BIN_WIDTH - judge::OK_WINDOW).abs();
+        let judgment = match center {
+            d if d <= judge::FLAWLESS_WINDOW => Judgment::Flawless,
+            d if d <= judge::PERFECT_WINDOW => Judgment::Perfect,
+            d if d <= judge::GOOD_WINDOW => Judgment::Good,
+            _ => Judgment::Ok,
+        };
+        let height = count * 90 / most;
+        canvas.set_draw_color(noteskin::judgment_color(judgment));
+        canvas.fill_rect(Rect::new(x + i as i32 * 11, y + 90 - height as i32, 10, height)).unwrap();
+    }
+}
+
+
+// Renders a line of text at (x, y), cutting it off at max_width
+#[allow(clippy::too_many_arguments)]
+fn draw_text(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+             text_cache: &mut TextCache,
+             font: &sdl2::ttf::Font,
+             text: &str,
+             color: sdl2::pixels::Color,
+             x: i32,
+             y: i32,
+             max_width: u32,
+            ) {
+    let texture = text_cache.texture(font, text, color);
+
+    let TextureQuery { width, height, .. } = texture.query();
+    let width = width.min(max_width);
+
+    canvas.copy(texture, Rect::new(0, 0, width, height), Rect::new(x, y, width, height)).unwrap();
+}
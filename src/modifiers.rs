@@ -0,0 +1,511 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::game_state::Arrow;
+use crate::input::LANES;
+use crate::timing::{self, ROWS_PER_BEAT};
+
+// Rates the player can pick, in steps of 0.1x
+const MIN_RATE: f64 = 0.5;
+const MAX_RATE: f64 = 2.0;
+
+
+// Lane permutations, lanes are left, down, up, right
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    Off,
+    Mirror,
+    Left,
+    Right,
+    // One random permutation for the whole chart
+    Shuffle,
+    // A new random permutation on every row
+    SuperShuffle,
+}
+
+
+impl Turn {
+    const ALL: [Turn; 6] = [Turn::Off, Turn::Mirror, Turn::Left, Turn::Right, Turn::Shuffle, Turn::SuperShuffle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Turn::Off => "Off",
+            Turn::Mirror => "Mirror",
+            Turn::Left => "Left",
+            Turn::Right => "Right",
+            Turn::Shuffle => "Shuffle",
+            Turn::SuperShuffle => "Super shuffle",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Turn::Off => "",
+            Turn::Mirror => "mirror",
+            Turn::Left => "left",
+            Turn::Right => "right",
+            Turn::Shuffle => "shuffle",
+            Turn::SuperShuffle => "super-shuffle",
+        }
+    }
+
+    // New lane of each lane, for the turns that don't depend on the seed
+    fn fixed_lanes(self) -> Option<[usize; LANES]> {
+        match self {
+            Turn::Off => Some([0, 1, 2, 3]),
+            Turn::Mirror => Some([3, 2, 1, 0]),
+            // Rotated a quarter turn: up goes left, left goes down...
+            Turn::Left => Some([1, 3, 0, 2]),
+            Turn::Right => Some([2, 0, 3, 1]),
+            Turn::Shuffle | Turn::SuperShuffle => None,
+        }
+    }
+}
+
+
+// Which way each lane scrolls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scroll {
+    Normal,
+    // Every lane scrolls up
+    Reverse,
+    // The right half of the lanes scrolls up
+    Split,
+    // Every other lane scrolls up
+    Alternate,
+}
+
+
+impl Scroll {
+    const ALL: [Scroll; 4] = [Scroll::Normal, Scroll::Reverse, Scroll::Split, Scroll::Alternate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scroll::Normal => "Normal",
+            Scroll::Reverse => "Reverse",
+            Scroll::Split => "Split",
+            Scroll::Alternate => "Alternate",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Scroll::Normal => "",
+            Scroll::Reverse => "reverse",
+            Scroll::Split => "split",
+            Scroll::Alternate => "alternate",
+        }
+    }
+}
+
+
+// Notes fading in or out along the way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fade {
+    Off,
+    // Notes vanish before the judgment line
+    Hidden,
+    // Notes only show up halfway
+    Sudden,
+    // Notes are never shown
+    Stealth,
+}
+
+
+impl Fade {
+    const ALL: [Fade; 4] = [Fade::Off, Fade::Hidden, Fade::Sudden, Fade::Stealth];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Fade::Off => "Off",
+            Fade::Hidden => "Hidden",
+            Fade::Sudden => "Sudden",
+            Fade::Stealth => "Stealth",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Fade::Off => "",
+            Fade::Hidden => "hidden",
+            Fade::Sudden => "sudden",
+            Fade::Stealth => "stealth",
+        }
+    }
+}
+
+
+// Steps through a list of options, wrapping at both ends
+fn cycle<T: Copy + PartialEq>(options: &[T], current: T, delta: f64) -> T {
+    let count = options.len() as i32;
+    let i = options.iter().position(|&option| option == current).unwrap_or(0) as i32;
+    options[(i + delta as i32).rem_euclid(count) as usize]
+}
+
+
+// Changes to the notes themselves. A play with any of these scores separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    // Only notes on a whole beat
+    Little,
+    NoHolds,
+    NoMines,
+    // Holds become rolls
+    Rolls,
+    // Taps become holds lasting until the next note
+    Planted,
+    // A mine half a beat after each tap, where the lane is free
+    Mines,
+}
+
+
+impl Transform {
+    pub const ALL: [Transform; 6] = [Transform::Little, Transform::NoHolds, Transform::NoMines, Transform::Rolls, Transform::Planted, Transform::Mines];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Transform::Little => "Little",
+            Transform::NoHolds => "No holds",
+            Transform::NoMines => "No mines",
+            Transform::Rolls => "Holds to rolls",
+            Transform::Planted => "Planted",
+            Transform::Mines => "Add mines",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Transform::Little => "little",
+            Transform::NoHolds => "noholds",
+            Transform::NoMines => "nomines",
+            Transform::Rolls => "rolls",
+            Transform::Planted => "planted",
+            Transform::Mines => "mines",
+        }
+    }
+}
+
+
+// How a chart is played, chosen from the song wheel and kept in the profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modifiers {
+    pub rate: f64, // music speed, 1.0 is the original
+    pub preserve_pitch: bool, // time-stretch instead of resampling when the rate isn't 1.0
+    pub turn: Turn,
+    pub scroll: Scroll,
+    pub fade: Fade,
+    pub transforms: [bool; Transform::ALL.len()], // indexed by Transform
+    // Shuffles are drawn from this, every play gets a new one and the replay keeps it
+    pub seed: u64,
+}
+
+
+impl Default for Modifiers {
+    fn default() -> Self {
+        Self { rate: 1.0, preserve_pitch: false, turn: Turn::Off, scroll: Scroll::Normal, fade: Fade::Off, transforms: [false; Transform::ALL.len()], seed: 0 }
+    }
+}
+
+
+impl Modifiers {
+    pub const ROWS: usize = 5 + Transform::ALL.len();
+
+    pub fn has(&self, transform: Transform) -> bool {
+        self.transforms[transform as usize]
+    }
+
+    // Space separated, e.g. "1.2x keep-pitch shuffle:5eed little". Default modifiers give an empty string
+    // so plays from before there were modifiers read back as 1.0x.
+    pub fn to_tag(self) -> String {
+        let mut parts = Vec::new();
+        if self.rate != 1.0 {
+            parts.push(format!("{:.1}x", self.rate));
+            if self.preserve_pitch {
+                parts.push("keep-pitch".to_string());
+            }
+        }
+        match self.turn {
+            Turn::Off => {}
+            Turn::Shuffle | Turn::SuperShuffle => parts.push(format!("{}:{:x}", self.turn.tag(), self.seed)),
+            turn => parts.push(turn.tag().to_string()),
+        }
+        parts.extend([self.scroll.tag(), self.fade.tag()].into_iter().filter(|tag| !tag.is_empty()).map(str::to_string));
+        for transform in Transform::ALL.into_iter().filter(|&transform| self.has(transform)) {
+            parts.push(transform.tag().to_string());
+        }
+        parts.join(" ")
+    }
+
+    pub fn from_tag(value: &str) -> Self {
+        let mut modifiers = Self::default();
+        for part in value.split_whitespace() {
+            let (name, seed) = part.split_once(':').unwrap_or((part, ""));
+            if let Some(&turn) = Turn::ALL.iter().find(|turn| turn.tag() == name && !name.is_empty()) {
+                modifiers.turn = turn;
+                modifiers.seed = u64::from_str_radix(seed, 16).unwrap_or(0);
+                continue;
+            }
+            if let Some(&scroll) = Scroll::ALL.iter().find(|scroll| scroll.tag() == part && !part.is_empty()) {
+                modifiers.scroll = scroll;
+                continue;
+            }
+            if let Some(&fade) = Fade::ALL.iter().find(|fade| fade.tag() == part && !part.is_empty()) {
+                modifiers.fade = fade;
+                continue;
+            }
+            if let Some(&transform) = Transform::ALL.iter().find(|transform| transform.tag() == part) {
+                modifiers.transforms[transform as usize] = true;
+                continue;
+            }
+
+            match part {
+                "keep-pitch" => modifiers.preserve_pitch = true,
+                _ => {
+                    if let Some(rate) = part.strip_suffix('x').and_then(|rate| rate.parse().ok()) {
+                        modifiers.rate = rate;
+                    }
+                }
+            }
+        }
+        modifiers
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let on_off = |on: bool| if on { "On" } else { "Off" };
+        let mut lines = vec![
+            format!("Rate: {:.1}x", self.rate),
+            format!("Keep pitch: {}", on_off(self.preserve_pitch)),
+            format!("Turn: {}", self.turn.name()),
+            format!("Scroll: {}", self.scroll.name()),
+            format!("Fade: {}", self.fade.name()),
+        ];
+        lines.extend(Transform::ALL.iter().map(|&transform| format!("{}: {}", transform.name(), on_off(self.has(transform)))));
+        lines
+    }
+
+    pub fn adjust(&mut self, row: usize, delta: f64) {
+        match row {
+            0 => self.rate = ((self.rate + delta * 0.1).clamp(MIN_RATE, MAX_RATE) * 10.0).round() / 10.0,
+            1 => self.preserve_pitch = !self.preserve_pitch,
+            2 => self.turn = cycle(&Turn::ALL, self.turn, delta),
+            3 => self.scroll = cycle(&Scroll::ALL, self.scroll, delta),
+            4 => self.fade = cycle(&Fade::ALL, self.fade, delta),
+            row => {
+                if let Some(on) = self.transforms.get_mut(row - 5) {
+                    *on = !*on;
+                }
+            }
+        }
+    }
+
+    // Whether plays with the two count for the same high scores: same rate and same transforms
+    pub fn same_scoring(&self, other: &Modifiers) -> bool {
+        self.rate == other.rate && self.transforms == other.transforms
+    }
+
+    // Whether the music has to be loaded again to go from one to the other
+    pub fn same_audio(&self, other: &Modifiers) -> bool {
+        self.rate == other.rate && self.preserve_pitch == other.preserve_pitch
+    }
+
+    // Applies the transforms to a chart, in chart time before any rate.
+    // `row_time` gives the time of a row, for the notes that get added.
+    pub fn apply_transforms(&self, arrows: &mut Vec<Arrow>, row_time: impl Fn(u32) -> f64) {
+        if self.has(Transform::NoMines) {
+            arrows.retain(|arrow| !arrow.mine);
+        }
+        if self.has(Transform::Little) {
+            arrows.retain(|arrow| arrow.quantization == 4);
+        }
+        for arrow in arrows.iter_mut().filter(|arrow| arrow.long) {
+            if self.has(Transform::NoHolds) {
+                arrow.long = false;
+                arrow.roll = false;
+            } else if self.has(Transform::Rolls) {
+                arrow.roll = true;
+            }
+        }
+
+        arrows.sort_by(|a, b| a.hit_time.total_cmp(&b.hit_time));
+
+        if self.has(Transform::Planted) {
+            let row_times: Vec<f64> = arrows.iter().filter(|arrow| !arrow.mine).map(|arrow| arrow.hit_time).collect();
+            for arrow in arrows.iter_mut().filter(|arrow| !arrow.mine && !arrow.long) {
+                let next = row_times.iter().copied().find(|&time| time > arrow.hit_time);
+                arrow.long = true;
+                arrow.end_time = next.unwrap_or_else(|| row_time(arrow.row + ROWS_PER_BEAT));
+            }
+        }
+
+        if self.has(Transform::Mines) {
+            let mut mines = Vec::new();
+            for arrow in arrows.iter().filter(|arrow| !arrow.mine && !arrow.long) {
+                let row = arrow.row + ROWS_PER_BEAT / 2;
+                let time = row_time(row);
+                // Nothing else in the lane up to and including the mine, and no hold running through it
+                let lane_free = arrows.iter().all(|other| {
+                    other.lane != arrow.lane
+                        || other.hit_time <= arrow.hit_time && (!other.long || other.end_time < time)
+                        || other.hit_time > time
+                });
+                if lane_free {
+                    mines.push(Arrow { hit_time: time, spawn_time: 0.0, long: false, end_time: 0.0, mine: true, roll: false, row, quantization: timing::quantization(row), ..arrow.clone() });
+                }
+            }
+            arrows.extend(mines);
+            arrows.sort_by(|a, b| a.hit_time.total_cmp(&b.hit_time));
+        }
+    }
+
+    // Moves the arrows to their lanes under the turn. Lanes past the pad's four are left alone.
+    pub fn apply_turn(&self, arrows: &mut Vec<Arrow>) {
+        let mut random = Random(self.seed);
+
+        if let Some(lanes) = self.turn.fixed_lanes().or_else(|| {
+            (self.turn == Turn::Shuffle).then(|| random.permutation())
+        }) {
+            for arrow in arrows.iter_mut().filter(|arrow| arrow.lane < LANES) {
+                arrow.lane = lanes[arrow.lane];
+            }
+            return;
+        }
+
+        // Super shuffle: every row gets its own permutation among the lanes no hold is still using
+        let mut order: Vec<usize> = (0..arrows.len()).filter(|&i| arrows[i].lane < LANES).collect();
+        order.sort_by(|&a, &b| arrows[a].hit_time.total_cmp(&arrows[b].hit_time));
+        let mut held_until = [f64::NEG_INFINITY; LANES];
+
+        let rows: Vec<Vec<usize>> = order.chunk_by(|&a, &b| arrows[a].hit_time == arrows[b].hit_time)
+            .map(|row| row.to_vec())
+            .collect();
+
+        // A row with more notes than free lanes loses the extra ones, mines first, instead of
+        // putting them under a hold
+        let mut dropped = vec![false; arrows.len()];
+        for mut row in rows {
+            let time = arrows[row[0]].hit_time;
+            let free: Vec<usize> = random.permutation().into_iter().filter(|&lane| held_until[lane] < time).collect();
+            row.sort_by_key(|&i| arrows[i].mine);
+
+            for (n, &i) in row.iter().enumerate() {
+                let Some(&lane) = free.get(n) else {
+                    dropped[i] = true;
+                    continue;
+                };
+                arrows[i].lane = lane;
+                if arrows[i].long {
+                    held_until[lane] = arrows[i].end_time;
+                }
+            }
+        }
+
+        let mut index = 0;
+        arrows.retain(|_| {
+            index += 1;
+            !dropped[index - 1]
+        });
+    }
+}
+
+
+// A seed for a new play's shuffles
+pub fn new_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+
+// SplitMix64, the same seed always gives the same lanes
+struct Random(u64);
+
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn permutation(&mut self) -> [usize; LANES] {
+        let mut lanes = [0, 1, 2, 3];
+        for i in (1..LANES).rev() {
+            lanes.swap(i, (self.next() % (i as u64 + 1)) as usize);
+        }
+        lanes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_tag_is_empty() {
+        assert_eq!(Modifiers::default().to_tag(), "");
+        assert_eq!(Modifiers::from_tag(""), Modifiers::default());
+    }
+
+    #[test]
+    fn rate_tag_round_trip() {
+        let modifiers = Modifiers { rate: 1.5, preserve_pitch: true, ..Modifiers::default() };
+
+        assert_eq!(modifiers.to_tag(), "1.5x keep-pitch");
+        assert_eq!(Modifiers::from_tag(&modifiers.to_tag()), modifiers);
+    }
+
+    #[test]
+    fn every_turn_round_trips() {
+        for turn in Turn::ALL {
+            let modifiers = Modifiers { turn, seed: 0xabc, ..Modifiers::default() };
+            let parsed = Modifiers::from_tag(&modifiers.to_tag());
+            assert_eq!(parsed.turn, turn);
+            if matches!(turn, Turn::Shuffle | Turn::SuperShuffle) {
+                assert_eq!(parsed.seed, 0xabc);
+            }
+        }
+    }
+
+    #[test]
+    fn transform_tags_round_trip() {
+        let mut modifiers = Modifiers::default();
+        modifiers.transforms[Transform::Little as usize] = true;
+        modifiers.transforms[Transform::Mines as usize] = true;
+
+        assert_eq!(modifiers.to_tag(), "little mines");
+        assert_eq!(Modifiers::from_tag(&modifiers.to_tag()), modifiers);
+    }
+
+    #[test]
+    fn scroll_and_fade_tags_round_trip() {
+        let modifiers = Modifiers { scroll: Scroll::Reverse, fade: Fade::Hidden, ..Modifiers::default() };
+
+        assert_eq!(modifiers.to_tag(), "reverse hidden");
+        assert_eq!(Modifiers::from_tag(&modifiers.to_tag()), modifiers);
+    }
+
+    #[test]
+    fn super_shuffle_keeps_off_held_lanes() {
+        let arrow = |lane, hit_time, mine| Arrow {
+            lane, hit_time, spawn_time: hit_time - 1.0, long: false, end_time: 0.0, mine, roll: false, row: 0, quantization: 4,
+        };
+        let hold = Arrow { long: true, end_time: 3.0, ..arrow(0, 1.0, false) };
+
+        for seed in 0..50 {
+            let modifiers = Modifiers { turn: Turn::SuperShuffle, seed, ..Modifiers::default() };
+            // A full row under the hold, and a row of two notes and two mines
+            let mut arrows = vec![hold.clone(), arrow(0, 2.0, false), arrow(1, 2.0, false), arrow(2, 2.0, false), arrow(3, 2.0, false),
+                                  arrow(0, 2.5, true), arrow(1, 2.5, false), arrow(2, 2.5, true), arrow(3, 2.5, false)];
+            modifiers.apply_turn(&mut arrows);
+
+            let held = arrows[0].lane;
+            let row = |time| arrows.iter().filter(move |arrow| arrow.hit_time == time);
+            assert_eq!(row(2.0).count(), 3);
+            assert!(row(2.0).all(|arrow| arrow.lane != held));
+            // The mine is dropped, not a note
+            assert_eq!(row(2.5).filter(|arrow| !arrow.mine).count(), 2);
+            assert_eq!(row(2.5).count(), 3);
+            assert!(row(2.5).all(|arrow| arrow.lane != held));
+        }
+    }
+}
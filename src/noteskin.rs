@@ -0,0 +1,250 @@
+use std::path::Path;
+
+use sdl2::image::LoadTexture;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use crate::config;
+use crate::score::Judgment;
+use crate::text::TextCache;
+
+
+// Color of each note value, anything finer than a 64th is grey
+const QUANTIZATION_COLORS: [(u32, Color); 8] = [
+    (4, Color::RGB(255, 70, 70)),
+    (8, Color::RGB(70, 130, 255)),
+    (12, Color::RGB(190, 90, 255)),
+    (16, Color::RGB(255, 225, 70)),
+    (24, Color::RGB(255, 120, 200)),
+    (32, Color::RGB(255, 160, 50)),
+    (48, Color::RGB(90, 230, 230)),
+    (64, Color::RGB(90, 220, 110)),
+];
+
+pub fn quantization_color(quantization: u32) -> Color {
+    QUANTIZATION_COLORS.iter()
+        .find(|(value, _)| *value == quantization)
+        .map_or(Color::RGB(170, 170, 170), |(_, color)| *color)
+}
+
+
+pub fn judgment_color(judgment: Judgment) -> Color {
+    match judgment {
+        Judgment::Flawless => Color::RGB(140, 230, 255),
+        Judgment::Perfect => Color::RGB(255, 215, 90),
+        Judgment::Good => Color::RGB(110, 230, 120),
+        Judgment::Ok => Color::RGB(200, 140, 255),
+        Judgment::Miss => Color::RGB(255, 80, 80),
+    }
+}
+
+
+// Color of the judgment text: hits outside the Flawless window show whether they were early or late
+pub fn timing_color(judgment: Judgment, offset: f64) -> Color {
+    match judgment {
+        Judgment::Flawless | Judgment::Miss => judgment_color(judgment),
+        _ if offset < 0.0 => Color::RGB(110, 180, 255),
+        _ => Color::RGB(255, 150, 90),
+    }
+}
+
+
+// Images of a noteskin folder, `noteskins/<name>/`:
+//   tap.png, hold_body.png, hold_cap.png, roll_body.png, roll_cap.png, mine.png,
+//   receptor.png, receptor_pressed.png and receptor_glow.png (drawn over the receptor on a hit),
+//   explosion.png (on a hit, tinted by judgment), hold_explosion.png (while a hold is held)
+//   and flawless.png, perfect.png, good.png, ok.png, miss.png for the judgment text
+// and an optional noteskin.txt. With #ROTATE:1; tap and receptor images are drawn for the
+// down arrow and turned to face each lane. Taps and caps are tinted by quantization, so they
+// should be drawn in white. Anything missing is drawn as a plain rectangle, or text.
+pub struct Noteskin<'a> {
+    tap: Option<Texture<'a>>,
+    hold_body: Option<Texture<'a>>,
+    hold_cap: Option<Texture<'a>>,
+    roll_body: Option<Texture<'a>>,
+    roll_cap: Option<Texture<'a>>,
+    mine: Option<Texture<'a>>,
+    receptor: Option<Texture<'a>>,
+    receptor_pressed: Option<Texture<'a>>,
+    receptor_glow: Option<Texture<'a>>,
+    explosion: Option<Texture<'a>>,
+    hold_explosion: Option<Texture<'a>>,
+    judgments: [Option<Texture<'a>>; 5], // in Judgment::ALL order
+    rotate: bool,
+}
+
+
+impl<'a> Noteskin<'a> {
+    pub fn load(texture_creator: &'a TextureCreator<WindowContext>, dir: &Path) -> Self {
+        let image = |name: &str| {
+            let path = dir.join(name);
+            if !path.exists() {
+                return None;
+            }
+            texture_creator.load_texture(&path)
+                .map_err(|e| eprintln!("Failed to load {}: {}", path.display(), e))
+                .ok()
+        };
+        let rotate = config::read_tags(&dir.join("noteskin.txt"))
+            .unwrap_or_default()
+            .iter()
+            .any(|(tag, value)| tag == "ROTATE" && value == "1");
+
+        Self {
+            tap: image("tap.png"),
+            hold_body: image("hold_body.png"),
+            hold_cap: image("hold_cap.png"),
+            roll_body: image("roll_body.png"),
+            roll_cap: image("roll_cap.png"),
+            mine: image("mine.png"),
+            receptor: image("receptor.png"),
+            receptor_pressed: image("receptor_pressed.png"),
+            receptor_glow: image("receptor_glow.png"),
+            explosion: image("explosion.png"),
+            hold_explosion: image("hold_explosion.png"),
+            judgments: Judgment::ALL.map(|judgment| image(&format!("{}.png", judgment.name().to_lowercase()))),
+            rotate,
+        }
+    }
+
+    // Turn from the down arrow, clockwise
+    fn angle(&self, lane: usize) -> f64 {
+        if !self.rotate {
+            return 0.0;
+        }
+        match lane % 4 {
+            0 => 90.0,
+            1 => 0.0,
+            2 => 180.0,
+            _ => 270.0,
+        }
+    }
+
+    // Arrow images are square, centered on the note's 50x25 rectangle at (x, y)
+    fn arrow_rect(x: i32, y: i32) -> Rect {
+        Rect::new(x, y - 12, 50, 50)
+    }
+
+    fn draw(canvas: &mut Canvas<Window>, texture: &mut Texture, rect: Rect, angle: f64, color: Color) {
+        texture.set_color_mod(color.r, color.g, color.b);
+        texture.set_alpha_mod(color.a);
+        canvas.copy_ex(texture, None, rect, angle, None, false, false).unwrap();
+    }
+
+    // `glow` is how much of the hit flash is left, 0 to 1
+    pub fn draw_receptor(&mut self, canvas: &mut Canvas<Window>, lane: usize, x: i32, y: i32, pressed: bool, glow: f64) {
+        let angle = self.angle(lane);
+        let image = if pressed && self.receptor_pressed.is_some() { &mut self.receptor_pressed } else { &mut self.receptor };
+        match image {
+            Some(texture) => Self::draw(canvas, texture, Self::arrow_rect(x, y), angle, Color::RGB(255, 255, 255)),
+            None => {
+                canvas.set_draw_color(if pressed { Color::RGB(200, 200, 255) } else { Color::RGB(100, 100, 100) });
+                canvas.fill_rect(Rect::new(x, y, 50, 25)).unwrap();
+            }
+        }
+
+        if glow > 0.0 {
+            let alpha = (glow * 255.0) as u8;
+            match &mut self.receptor_glow {
+                Some(texture) => Self::draw(canvas, texture, Self::arrow_rect(x, y), angle, Color::RGBA(255, 255, 255, alpha)),
+                None => {
+                    canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha / 2));
+                    canvas.fill_rect(Rect::new(x - 4, y - 4, 58, 33)).unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn draw_tap(&mut self, canvas: &mut Canvas<Window>, lane: usize, x: i32, y: i32, color: Color) {
+        let angle = self.angle(lane);
+        match &mut self.tap {
+            Some(texture) => Self::draw(canvas, texture, Self::arrow_rect(x, y), angle, color),
+            None => {
+                canvas.set_draw_color(color);
+                canvas.fill_rect(Rect::new(x, y, 50, 25)).unwrap();
+            }
+        }
+    }
+
+    pub fn draw_mine(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, alpha: u8) {
+        match &mut self.mine {
+            Some(texture) => Self::draw(canvas, texture, Self::arrow_rect(x, y), 0.0, Color::RGBA(255, 255, 255, alpha)),
+            None => {
+                canvas.set_draw_color(Color::RGBA(255, 60, 60, alpha));
+                canvas.fill_rect(Rect::new(x + 10, y + 2, 30, 21)).unwrap();
+            }
+        }
+    }
+
+    // A piece of a hold or roll body between `top` and `top + height`
+    pub fn draw_body(&mut self, canvas: &mut Canvas<Window>, roll: bool, x: i32, top: i32, height: u32, color: Color) {
+        let image = if roll { &mut self.roll_body } else { &mut self.hold_body };
+        match image {
+            Some(texture) => Self::draw(canvas, texture, Rect::new(x + 5, top, 40, height), 0.0, color),
+            None => {
+                canvas.set_draw_color(color);
+                canvas.fill_rect(Rect::new(x + 10, top, 30, height)).unwrap();
+            }
+        }
+    }
+
+    // The end of a hold or roll, centered on `center_y`
+    pub fn draw_cap(&mut self, canvas: &mut Canvas<Window>, roll: bool, x: i32, center_y: i32, color: Color) {
+        let image = if roll { &mut self.roll_cap } else { &mut self.hold_cap };
+        match image {
+            Some(texture) => Self::draw(canvas, texture, Rect::new(x + 5, center_y - 10, 40, 20), 0.0, color),
+            None => {
+                canvas.set_draw_color(color);
+                canvas.fill_rect(Rect::new(x + 5, center_y - 5, 40, 10)).unwrap();
+            }
+        }
+    }
+
+    // `age` runs from 0 at the hit to 1 when the explosion is gone
+    pub fn draw_explosion(&mut self, canvas: &mut Canvas<Window>, lane: usize, x: i32, y: i32, color: Color, age: f64) {
+        let angle = self.angle(lane);
+        let alpha = ((1.0 - age) * 255.0) as u8;
+        let grow = (age * 16.0) as i32;
+        match &mut self.explosion {
+            Some(texture) => {
+                let rect = Rect::new(x - 10 - grow, y - 22 - grow, (70 + 2 * grow) as u32, (70 + 2 * grow) as u32);
+                Self::draw(canvas, texture, rect, angle, Color::RGBA(color.r, color.g, color.b, alpha));
+            }
+            None => {
+                canvas.set_draw_color(Color::RGBA(color.r, color.g, color.b, alpha / 2));
+                canvas.fill_rect(Rect::new(x - grow, y - grow, (50 + 2 * grow) as u32, (25 + 2 * grow) as u32)).unwrap();
+            }
+        }
+    }
+
+    // `pulse` goes from 0 to 1 and back while the hold is held
+    pub fn draw_hold_explosion(&mut self, canvas: &mut Canvas<Window>, lane: usize, x: i32, y: i32, pulse: f64) {
+        let angle = self.angle(lane);
+        let alpha = (140.0 + pulse * 115.0) as u8;
+        match &mut self.hold_explosion {
+            Some(texture) => Self::draw(canvas, texture, Rect::new(x - 10, y - 22, 70, 70), angle, Color::RGBA(255, 255, 255, alpha)),
+            None => {
+                canvas.set_draw_color(Color::RGBA(200, 200, 255, alpha / 3));
+                canvas.fill_rect(Rect::new(x - 6, y - 6, 62, 37)).unwrap();
+            }
+        }
+    }
+
+    // Judgment image, or its name in `font`, centered on `center_x` and scaled by `scale`
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_judgment(&mut self, canvas: &mut Canvas<Window>, text_cache: &mut TextCache, font: &sdl2::ttf::Font, judgment: Judgment, color: Color, center_x: i32, y: i32, scale: f64) {
+        let image = &mut self.judgments[Judgment::ALL.iter().position(|&j| j == judgment).unwrap_or(0)];
+        match image {
+            Some(texture) => Self::draw_centered(canvas, texture, center_x, y, scale, color),
+            None => Self::draw_centered(canvas, text_cache.texture(font, judgment.name(), Color::RGB(255, 255, 255)), center_x, y, scale, color),
+        }
+    }
+
+    fn draw_centered(canvas: &mut Canvas<Window>, texture: &mut Texture, center_x: i32, y: i32, scale: f64, color: Color) {
+        let query = texture.query();
+        let (width, height) = ((query.width as f64 * scale) as u32, (query.height as f64 * scale) as u32);
+        Self::draw(canvas, texture, Rect::new(center_x - width as i32 / 2, y - height as i32 / 2, width, height), 0.0, color);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::display;
+use crate::input::LANES;
+use crate::modifiers::{Fade, Modifiers, Scroll};
+use crate::theme::Theme;
+
+const SCREEN_HEIGHT: f64 = display::HEIGHT as f64;
+// Top edge of the receptors and of a note sitting on them, without reverse
+const JUDGMENT_Y: f64 = 550.0;
+// Top edge of a note when it spawns
+const SPAWN_Y: f64 = -50.0;
+pub const NOTE_HEIGHT: f64 = 25.0;
+
+
+// Where a note is drawn and how much of it shows
+pub struct NoteRender {
+    pub y: f64, // top edge
+    pub alpha: u8,
+}
+
+
+// Turns how far a note has travelled into where and how it is drawn. Every scroll
+// and fade modifier is a change to this transform rather than its own drawing code.
+pub struct Playfield {
+    scroll: Scroll,
+    fade: Fade,
+    // The theme's distances of the HUD from the judgment line
+    combo_distance: f64,
+    judgment_distance: f64,
+    error_bar_distance: f64,
+}
+
+
+impl Playfield {
+    pub fn new(modifiers: &Modifiers, theme: &Theme) -> Self {
+        Self {
+            scroll: modifiers.scroll,
+            fade: modifiers.fade,
+            combo_distance: theme.combo_distance,
+            judgment_distance: theme.judgment_distance,
+            error_bar_distance: theme.error_bar_distance,
+        }
+    }
+
+    // Reversed lanes scroll up towards receptors at the top
+    pub fn reversed(&self, lane: usize) -> bool {
+        match self.scroll {
+            Scroll::Normal => false,
+            Scroll::Reverse => true,
+            Scroll::Split => lane >= LANES / 2,
+            Scroll::Alternate => lane % 2 == 1,
+        }
+    }
+
+    fn flip(&self, lane: usize, y: f64) -> f64 {
+        if self.reversed(lane) {
+            SCREEN_HEIGHT - NOTE_HEIGHT - y
+        } else {
+            y
+        }
+    }
+
+    pub fn judgment_y(&self, lane: usize) -> f64 {
+        self.flip(lane, JUDGMENT_Y)
+    }
+
+    // Text `distance` before the judgment line, on the receptors' side of the screen
+    fn hud_y(&self, distance: f64) -> f64 {
+        if (0..LANES).all(|lane| self.reversed(lane)) {
+            self.flip(0, JUDGMENT_Y - distance)
+        } else {
+            JUDGMENT_Y - distance
+        }
+    }
+
+    pub fn combo_y(&self) -> f64 {
+        self.hud_y(self.combo_distance)
+    }
+
+    pub fn judgment_text_y(&self) -> f64 {
+        self.hud_y(self.judgment_distance)
+    }
+
+    pub fn error_bar_y(&self) -> f64 {
+        self.hud_y(self.error_bar_distance)
+    }
+
+    // `progress` is 0 when the note spawns and 1 when it reaches the judgment line
+    pub fn note(&self, lane: usize, progress: f64) -> NoteRender {
+        NoteRender {
+            y: self.flip(lane, SPAWN_Y + progress * (JUDGMENT_Y - SPAWN_Y)),
+            alpha: (self.visibility(progress) * 255.0) as u8,
+        }
+    }
+
+    pub fn on_screen(y: f64) -> bool {
+        y > -NOTE_HEIGHT && y < SCREEN_HEIGHT
+    }
+
+    fn visibility(&self, progress: f64) -> f64 {
+        match self.fade {
+            Fade::Off => 1.0,
+            // Gone before the judgment line
+            Fade::Hidden => 1.0 - ((progress - 0.55) / 0.2).clamp(0.0, 1.0),
+            // Appears halfway down
+            Fade::Sudden => ((progress - 0.25) / 0.2).clamp(0.0, 1.0),
+            Fade::Stealth => 0.0,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::modifiers::Modifiers;
+
+
+// A stretch of a chart played over and over, picked by measure or by #LABELS section
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Practice {
+    pub from: u32, // first measure
+    pub to: u32, // measure the section stops at, not played
+    pub modifiers: Modifiers,
+}
+
+
+impl Practice {
+    pub const ROWS: usize = 3 + Modifiers::ROWS;
+
+    pub fn new(measures: u32, modifiers: Modifiers) -> Self {
+        Self { from: 0, to: measures.max(1), modifiers }
+    }
+
+    // (first measure, name) of each label. A section runs until the next label.
+    pub fn sections(labels: &[(f64, String)]) -> Vec<(u32, String)> {
+        let mut sections: Vec<(u32, String)> = labels.iter()
+            .map(|(beat, name)| ((beat / 4.0).floor().max(0.0) as u32, name.clone()))
+            .collect();
+        sections.sort_by_key(|(measure, _)| *measure);
+        sections.dedup_by_key(|(measure, _)| *measure);
+        sections
+    }
+
+    fn section_index(&self, sections: &[(u32, String)], measures: u32) -> Option<usize> {
+        (0..sections.len()).find(|&i| {
+            let end = sections.get(i + 1).map_or(measures, |(measure, _)| *measure);
+            sections[i].0 == self.from && end == self.to
+        })
+    }
+
+    pub fn lines(&self, sections: &[(u32, String)], measures: u32) -> Vec<String> {
+        let section = match self.section_index(sections, measures) {
+            Some(i) => sections[i].1.clone(),
+            None if sections.is_empty() => "No labels".to_string(),
+            None => "Custom".to_string(),
+        };
+
+        let mut lines = vec![
+            format!("Section: {}", section),
+            format!("From measure: {}", self.from + 1),
+            format!("To measure: {}", self.to),
+        ];
+        lines.extend(self.modifiers.lines());
+        lines
+    }
+
+    pub fn adjust(&mut self, row: usize, delta: i32, sections: &[(u32, String)], measures: u32) {
+        match row {
+            0 if !sections.is_empty() => {
+                let count = sections.len() as i32;
+                let i = match self.section_index(sections, measures) {
+                    Some(i) => (i as i32 + delta).rem_euclid(count),
+                    None if delta > 0 => 0,
+                    None => count - 1,
+                } as usize;
+                self.from = sections[i].0.min(measures.saturating_sub(1));
+                self.to = sections.get(i + 1).map_or(measures, |(measure, _)| *measure).max(self.from + 1);
+            }
+            0 => {}
+            1 => self.from = self.from.saturating_add_signed(delta).min(self.to - 1),
+            2 => self.to = self.to.saturating_add_signed(delta).clamp(self.from + 1, measures.max(1)),
+            _ => self.modifiers.adjust(row - 3, delta as f64),
+        }
+    }
+}
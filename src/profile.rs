@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::modifiers::Modifiers;
+use crate::score::{Judgment, PlayResult};
+
+
+// A local player. Settings, bindings and scores all live in the profile's own folder.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub dir: PathBuf,
+
+    pub speed: f64, // seconds from spawn to the judgment line, higher = slower
+    pub offset: f64, // seconds, positive delays the notes
+    pub noteskin: String,
+    pub modifiers: Modifiers,
+    pub translit: bool, // show transliterated titles and artists where the simfile has them
+    pub error_bar: bool, // show recent hit offsets during play
+
+    pub plays: u32,
+    pub play_time: f64,
+    pub notes_hit: u32,
+}
+
+
+impl Profile {
+    fn profiles_dir() -> PathBuf {
+        config::data_dir().join("profiles")
+    }
+
+    fn new(name: &str, dir: &Path) -> Self {
+        Self {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            speed: 1.5,
+            offset: 0.0,
+            noteskin: "default".to_string(),
+            modifiers: Modifiers::default(),
+            translit: false,
+            error_bar: false,
+            plays: 0,
+            play_time: 0.0,
+            notes_hit: 0,
+        }
+    }
+
+    pub fn list() -> Vec<Profile> {
+        let Ok(entries) = fs::read_dir(Self::profiles_dir()) else {
+            return Vec::new();
+        };
+
+        let mut profiles: Vec<Profile> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Self::load(&entry.path()))
+            .collect();
+        profiles.sort_by_key(|profile| profile.name.to_lowercase());
+        profiles
+    }
+
+    pub fn create(name: &str) -> Option<Profile> {
+        let name = name.trim();
+        let dir_name: String = name.chars()
+            .filter(|c| c.is_alphanumeric() || " -_".contains(*c))
+            .collect();
+        if dir_name.trim().is_empty() {
+            return None;
+        }
+
+        let dir = Self::profiles_dir().join(dir_name.trim());
+        if dir.exists() {
+            return Self::load(&dir);
+        }
+        fs::create_dir_all(&dir).ok()?;
+
+        // Bindings and scores from before there were profiles go to the first one made
+        if Self::list().is_empty() {
+            for file in ["bindings.cfg", "scores.txt"] {
+                let old = config::data_dir().join(file);
+                if old.exists() {
+                    fs::rename(&old, dir.join(file)).ok();
+                }
+            }
+        }
+
+        let profile = Self::new(name, &dir);
+        profile.save();
+        Some(profile)
+    }
+
+    fn load(dir: &Path) -> Option<Profile> {
+        let tags = config::read_tags(&dir.join("profile.txt"))?;
+        let mut profile = Self::new("", dir);
+
+        for (tag, value) in tags {
+            match tag.as_str() {
+                "NAME" => profile.name = value,
+                "SPEED" => profile.speed = value.parse().unwrap_or(profile.speed),
+                "OFFSET" => profile.offset = value.parse().unwrap_or(profile.offset),
+                "NOTESKIN" => profile.noteskin = value,
+                "MODIFIERS" => profile.modifiers = Modifiers::from_tag(&value),
+                "TRANSLIT" => profile.translit = value == "1",
+                "ERRORBAR" => profile.error_bar = value == "1",
+                "PLAYS" => profile.plays = value.parse().unwrap_or(0),
+                "PLAYTIME" => profile.play_time = value.parse().unwrap_or(0.0),
+                "NOTESHIT" => profile.notes_hit = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        Some(profile)
+    }
+
+    pub fn save(&self) {
+        let tags = [
+            ("NAME", self.name.clone()),
+            ("SPEED", self.speed.to_string()),
+            ("OFFSET", self.offset.to_string()),
+            ("NOTESKIN", self.noteskin.clone()),
+            ("MODIFIERS", self.modifiers.to_tag()),
+            ("TRANSLIT", (self.translit as u8).to_string()),
+            ("ERRORBAR", (self.error_bar as u8).to_string()),
+            ("PLAYS", self.plays.to_string()),
+            ("PLAYTIME", format!("{:.0}", self.play_time)),
+            ("NOTESHIT", self.notes_hit.to_string()),
+        ].map(|(tag, value)| (tag.to_string(), value));
+        config::write_tags(&self.dir.join("profile.txt"), &tags);
+    }
+
+    pub fn bindings_path(&self) -> PathBuf {
+        self.dir.join("bindings.cfg")
+    }
+
+    pub fn scores_path(&self) -> PathBuf {
+        self.dir.join("scores.txt")
+    }
+
+    pub fn replays_path(&self) -> PathBuf {
+        self.dir.join("replays")
+    }
+
+    pub fn record_play(&mut self, result: &PlayResult, seconds: f64) {
+        self.plays += 1;
+        self.play_time += seconds;
+        self.notes_hit += Judgment::ALL.iter()
+            .filter(|&&judgment| judgment != Judgment::Miss)
+            .map(|&judgment| result.count(judgment))
+            .sum::<u32>();
+        self.save();
+    }
+
+    pub fn stats_string(&self) -> String {
+        let minutes = (self.play_time / 60.0) as u32;
+        format!("{} plays, {}:{:02} played, {} notes hit", self.plays, minutes / 60, minutes % 60, self.notes_hit)
+    }
+}
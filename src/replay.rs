@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::game_state::Arrow;
+
+// How long autoplay keeps a tap pressed
+const AUTOPLAY_TAP_LENGTH: f64 = 0.05;
+// Time between autoplay's taps on a roll
+const AUTOPLAY_ROLL_INTERVAL: f64 = 0.1;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayEvent {
+    pub time: f64, // chart time in seconds
+    pub lane: usize,
+    pub pressed: bool,
+}
+
+
+// The lane presses and releases of one play, enough to run it through the judge again
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    pub chart: u64,
+    pub modifiers: String,
+    pub events: Vec<ReplayEvent>,
+    // Chart times the player paused at
+    pub pauses: Vec<f64>,
+}
+
+
+impl Replay {
+    pub fn new(chart: u64, modifiers: &str) -> Self {
+        Self { chart, modifiers: modifiers.to_string(), events: Vec::new(), pauses: Vec::new() }
+    }
+
+    // Perfect input for a chart: every note pressed on its hit time, holds kept down until
+    // their end, rolls tapped through, and each lane let go before the next mine or note in it
+    pub fn autoplay(chart: u64, modifiers: &str, arrows: &[Arrow]) -> Self {
+        let mut replay = Self::new(chart, modifiers);
+
+        for arrow in arrows.iter().filter(|arrow| !arrow.mine) {
+            let next_in_lane = arrows.iter()
+                .filter(|other| other.lane == arrow.lane && other.hit_time > arrow.hit_time)
+                .map(|other| other.hit_time)
+                .fold(f64::INFINITY, f64::min);
+
+            if arrow.roll {
+                let mut tap = arrow.hit_time;
+                while tap < arrow.end_time {
+                    replay.record(tap, arrow.lane, true);
+                    replay.record(tap + AUTOPLAY_TAP_LENGTH, arrow.lane, false);
+                    tap += AUTOPLAY_ROLL_INTERVAL;
+                }
+                continue;
+            }
+
+            let release = if arrow.long { arrow.end_time } else { arrow.hit_time + AUTOPLAY_TAP_LENGTH };
+            replay.record(arrow.hit_time, arrow.lane, true);
+            replay.record(release.min(next_in_lane - 0.001).max(arrow.hit_time), arrow.lane, false);
+        }
+
+        // Releases go first on a tie so a lane is free again before it is pressed
+        replay.events.sort_by(|a, b| a.time.total_cmp(&b.time).then(a.pressed.cmp(&b.pressed)));
+        replay
+    }
+
+    pub fn record(&mut self, time: f64, lane: usize, pressed: bool) {
+        self.events.push(ReplayEvent { time, lane, pressed });
+    }
+
+    // Replays are named <chart hash>-<unix time>.txt so the newest one sorts last
+    pub fn save(&self, dir: &Path) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Failed to create replay directory {}: {}", dir.display(), e);
+            return;
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        // f64 Display round-trips exactly, which keeps the replayed judgments identical
+        let events = self.events.iter()
+            .map(|event| format!("{}:{}:{}", event.time, event.lane, if event.pressed { 'D' } else { 'U' }))
+            .collect::<Vec<_>>()
+            .join(",");
+        let pauses = self.pauses.iter().map(|time| time.to_string()).collect::<Vec<_>>().join(",");
+        let tags = [
+            ("CHART", format!("{:016x}", self.chart)),
+            ("MODIFIERS", self.modifiers.clone()),
+            ("EVENTS", events),
+            ("PAUSES", pauses),
+        ].map(|(tag, value)| (tag.to_string(), value));
+
+        config::write_tags(&dir.join(format!("{:016x}-{:012}.txt", self.chart, time)), &tags);
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let mut replay = Self::default();
+        for (tag, value) in config::read_tags(path)? {
+            match tag.as_str() {
+                "CHART" => replay.chart = u64::from_str_radix(&value, 16).ok()?,
+                "MODIFIERS" => replay.modifiers = value,
+                "EVENTS" => {
+                    for event in value.split(',').filter(|event| !event.is_empty()) {
+                        let mut parts = event.split(':');
+                        replay.events.push(ReplayEvent {
+                            time: parts.next()?.parse().ok()?,
+                            lane: parts.next()?.parse().ok()?,
+                            pressed: parts.next()? == "D",
+                        });
+                    }
+                }
+                "PAUSES" => replay.pauses = value.split(',').filter_map(|time| time.parse().ok()).collect(),
+                _ => {}
+            }
+        }
+        Some(replay)
+    }
+
+    pub fn latest(dir: &Path, chart: u64) -> Option<Self> {
+        let prefix = format!("{:016x}-", chart);
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir).ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix)))
+            .collect();
+        paths.sort();
+        Self::load(paths.last()?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state::GameState;
+    use crate::judge::Judge;
+    use crate::modifiers::Modifiers;
+    use crate::score::Judgment;
+
+    // One measure of taps, then a hold and a mine, at 120 BPM
+    const CHART: &str = "#TITLE:Test;\n#OFFSET:0;\n#BPMS:0=120;\n#NOTES:\n     dance-single:\n     :\n     Beginner:\n     1:\n     0,0,0,0,0:\n\
+        1000\n0100\n0010\n0001\n,\n2000\n0000\n3000\n0M00\n;\n";
+
+    fn run(judge: &mut Judge, events: &[ReplayEvent]) {
+        for event in events {
+            if event.pressed {
+                judge.press(event.lane, event.time);
+            } else {
+                judge.release(event.lane, event.time);
+            }
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_the_result() {
+        let mut game_state = GameState::from_sm(CHART, 1.0);
+        game_state.start(0, &Modifiers::default());
+
+        let inputs = [
+            (0.01, 0, true), (0.06, 0, false),
+            (0.52, 1, true), (0.6, 1, false),
+            (1.08, 2, true), (1.12, 2, false),
+            (1.99, 0, true), (3.0, 0, false),
+            (3.4, 1, true), (3.6, 1, false),
+        ];
+
+        // Played live, judged once a frame as the inputs come in
+        let mut replay = Replay::new(game_state.chart_hashes[0], "");
+        let mut live = Judge::new(&game_state.arrows);
+        let mut next = 0;
+        for frame in 0..300 {
+            let time = frame as f64 / 60.0;
+            while let Some(&(at, lane, pressed)) = inputs.get(next).filter(|input| input.0 <= time) {
+                run(&mut live, &[ReplayEvent { time: at, lane, pressed }]);
+                replay.record(at, lane, pressed);
+                next += 1;
+            }
+            live.update(time);
+        }
+        assert_eq!(live.result.count(Judgment::Flawless), 3);
+        assert_eq!(live.result.count(Judgment::Good), 1);
+        assert_eq!(live.result.count(Judgment::Miss), 1);
+        assert_eq!((live.result.holds_ok, live.result.mines_hit), (1, 1));
+
+        // Saved, loaded and judged again without any frames in between
+        let dir = std::env::temp_dir().join(format!("rustrhythm-replay-test-{}", std::process::id()));
+        replay.save(&dir);
+        let loaded = Replay::latest(&dir, replay.chart);
+        fs::remove_dir_all(&dir).ok();
+        let loaded = loaded.expect("replay was saved");
+
+        let mut replayed = Judge::new(&game_state.arrows);
+        run(&mut replayed, &loaded.events);
+        replayed.update(5.0);
+        assert_eq!(replayed.result, live.result);
+    }
+
+    #[test]
+    fn autoplay_is_perfect() {
+        // Adds a roll next to the hold and a tap right after the hold in its lane
+        let chart = CHART.replace("2000\n0000\n3000\n0M00", "2400\n0000\n3300\n1M00");
+        let mut game_state = GameState::from_sm(&chart, 1.0);
+        game_state.start(0, &Modifiers::default());
+
+        let replay = Replay::autoplay(game_state.chart_hashes[0], "", &game_state.arrows);
+        let mut judge = Judge::new(&game_state.arrows);
+        run(&mut judge, &replay.events);
+        judge.update(5.0);
+
+        assert_eq!(judge.result.count(Judgment::Flawless), 7);
+        assert_eq!(judge.result.max_combo, 7);
+        assert_eq!((judge.result.holds_ok, judge.result.holds_ng), (2, 0));
+        assert_eq!(judge.result.mines_hit, 0);
+    }
+}
@@ -0,0 +1,142 @@
+// Hits needed before the results suggest an offset
+const MIN_OFFSET_SAMPLES: usize = 20;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Judgment {
+    Flawless,
+    Perfect,
+    Good,
+    Ok,
+    Miss,
+}
+
+
+impl Judgment {
+    pub const ALL: [Judgment; 5] = [Judgment::Flawless, Judgment::Perfect, Judgment::Good, Judgment::Ok, Judgment::Miss];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Judgment::Flawless => "Flawless",
+            Judgment::Perfect => "Perfect",
+            Judgment::Good => "Good",
+            Judgment::Ok => "OK",
+            Judgment::Miss => "Miss",
+        }
+    }
+
+    pub fn points(self) -> i32 {
+        match self {
+            Judgment::Flawless => 500,
+            Judgment::Perfect => 300,
+            Judgment::Good => 200,
+            Judgment::Ok => 100,
+            Judgment::Miss => 0,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+
+// Everything that happened during one play of a chart
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayResult {
+    pub judgments: [u32; 5],
+    pub combo: u32,
+    pub max_combo: u32,
+    pub holds_ok: u32,
+    pub holds_ng: u32,
+    pub mines_hit: u32,
+    pub score: i32,
+    // Seconds each hit tap was off by, late is positive. Not kept with high scores.
+    pub offsets: Vec<f64>,
+}
+
+
+impl PlayResult {
+    pub fn judge(&mut self, judgment: Judgment) {
+        self.judgments[judgment.index()] += 1;
+        self.score += judgment.points();
+
+        if judgment == Judgment::Miss {
+            self.combo = 0;
+        } else {
+            self.combo += 1;
+            self.max_combo = self.max_combo.max(self.combo);
+        }
+    }
+
+    pub fn hold(&mut self, ok: bool) {
+        if ok {
+            self.holds_ok += 1;
+            self.score += Judgment::Perfect.points();
+        } else {
+            self.holds_ng += 1;
+        }
+    }
+
+    pub fn mean_offset(&self) -> Option<f64> {
+        if self.offsets.is_empty() {
+            return None;
+        }
+        Some(self.offsets.iter().sum::<f64>() / self.offsets.len() as f64)
+    }
+
+    pub fn offset_deviation(&self) -> Option<f64> {
+        let mean = self.mean_offset()?;
+        let variance = self.offsets.iter().map(|offset| (offset - mean).powi(2)).sum::<f64>() / self.offsets.len() as f64;
+        Some(variance.sqrt())
+    }
+
+    // Change to the player's offset that would center their hits, once there are enough to go by
+    pub fn suggested_offset(&self) -> Option<f64> {
+        if self.offsets.len() < MIN_OFFSET_SAMPLES {
+            return None;
+        }
+        // Hitting late means the notes come too early, so they should be delayed by as much
+        self.mean_offset().filter(|mean| mean.abs() >= 0.003)
+    }
+
+    pub fn count(&self, judgment: Judgment) -> u32 {
+        self.judgments[judgment.index()]
+    }
+
+    // Taps count by their judgment, a hold held to the end counts as a Flawless and a hit mine takes half of one off
+    pub fn percentage(&self) -> f64 {
+        let taps: u32 = self.judgments.iter().sum();
+        let possible = (taps + self.holds_ok + self.holds_ng) as f64 * Judgment::Flawless.points() as f64;
+        if possible == 0.0 {
+            return 0.0;
+        }
+
+        let earned = Judgment::ALL.iter()
+            .map(|&j| (self.count(j) as i32 * j.points()) as f64)
+            .sum::<f64>()
+            + (self.holds_ok as i32 * Judgment::Flawless.points()) as f64
+            - (self.mines_hit as i32 * Judgment::Flawless.points() / 2) as f64;
+
+        (earned / possible * 100.0).clamp(0.0, 100.0)
+    }
+
+    pub fn grade(&self) -> &'static str {
+        match self.percentage() {
+            p if p >= 100.0 => "AAA",
+            p if p >= 93.0 => "AA",
+            p if p >= 80.0 => "A",
+            p if p >= 65.0 => "B",
+            p if p >= 45.0 => "C",
+            _ => "D",
+        }
+    }
+
+    pub fn full_combo(&self) -> bool {
+        self.count(Judgment::Miss) == 0 && self.holds_ng == 0
+    }
+
+    pub fn perfect_combo(&self) -> bool {
+        self.full_combo() && self.count(Judgment::Good) == 0 && self.count(Judgment::Ok) == 0
+    }
+}
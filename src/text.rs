@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Texture, TextureCreator};
+use sdl2::surface::Surface;
+use sdl2::ttf::Font;
+use sdl2::video::WindowContext;
+
+// Strings kept per font and color before the least recently used ones are dropped
+const CAPACITY: usize = 256;
+
+
+// Font (its address, fonts live for the whole run) and color
+type StyleKey = (usize, (u8, u8, u8, u8));
+
+
+struct Entry<'a> {
+    texture: Texture<'a>,
+    last_used: u64,
+}
+
+
+// Rendered strings, so text that stays the same from one frame to the next is only
+// rendered once. One cache is made at startup and handed to every screen.
+pub struct TextCache<'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    entries: HashMap<StyleKey, HashMap<String, Entry<'a>>>,
+    uses: u64,
+}
+
+
+impl<'a> TextCache<'a> {
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Self {
+        Self { texture_creator, entries: HashMap::new(), uses: 0 }
+    }
+
+    // Texture of `text` in `font` and `color`, rendered the first time it is asked for.
+    // Callers may tint or fade it for a draw, it comes back without either every time.
+    pub fn texture(&mut self, font: &Font, text: &str, color: Color) -> &mut Texture<'a> {
+        self.uses += 1;
+        let uses = self.uses;
+        let texts = self.entries.entry((font as *const Font as usize, color.rgba())).or_default();
+
+        if !texts.contains_key(text) {
+            if texts.len() >= CAPACITY {
+                let mut ages: Vec<u64> = texts.values().map(|entry| entry.last_used).collect();
+                ages.sort_unstable();
+                let cutoff = ages[ages.len() / 2];
+                texts.retain(|_, entry| entry.last_used > cutoff);
+            }
+            // SDL_ttf fails on an empty string, that gets a single transparent pixel
+            let surface = if text.is_empty() {
+                Surface::new(1, 1, PixelFormatEnum::RGBA32).unwrap()
+            } else {
+                font.render(text).blended(color).map_err(|e| e.to_string()).unwrap()
+            };
+            let texture = self.texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string()).unwrap();
+            texts.insert(text.to_string(), Entry { texture, last_used: uses });
+        }
+
+        let entry = texts.get_mut(text).unwrap();
+        entry.last_used = uses;
+        entry.texture.set_color_mod(255, 255, 255);
+        entry.texture.set_alpha_mod(255);
+        &mut entry.texture
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use sdl2::pixels::Color;
+
+use crate::config;
+
+// Fonts, colors and positions of every screen, read from `themes/<name>/theme.txt`.
+// Anything the file leaves out keeps the look the game has always had.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub font: PathBuf,
+    pub font_size: u16,
+    pub small_font_size: u16,
+
+    pub text: Color,
+    pub subtext: Color,
+    pub accent: Color, // grades, mode and countdowns
+    pub selected: Color,
+    pub unselected: Color,
+    pub menu_background: Color, // screens without a song background
+    pub list_item: Color, // song wheel rows
+    pub panel: Color, // filter and modifier panels
+    pub background_dim: u8, // alpha of the black laid over song backgrounds
+
+    // Gameplay
+    pub playfield_x: i32,
+    pub playfield_width: u32,
+    pub score_position: (i32, i32),
+    pub mode_position: (i32, i32),
+    pub modifiers_position: (i32, i32),
+    pub combo_color: Color,
+    // Distances from the judgment line, towards where the notes come from
+    pub combo_distance: f64,
+    pub judgment_distance: f64,
+    pub error_bar_distance: f64,
+
+    // Difficulty list
+    pub difficulty_title_y: i32,
+    pub difficulty_position: (i32, i32), // first item
+    pub difficulty_size: (u32, u32),
+    pub difficulty_spacing: i32,
+}
+
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            font: PathBuf::from("./assets/OpenSans.ttf"),
+            font_size: 32,
+            small_font_size: 20,
+            text: Color::RGB(255, 255, 255),
+            subtext: Color::RGB(180, 180, 190),
+            accent: Color::RGB(255, 215, 90),
+            selected: Color::RGB(200, 200, 255),
+            unselected: Color::RGB(100, 100, 100),
+            menu_background: Color::RGB(20, 20, 30),
+            list_item: Color::RGB(60, 60, 70),
+            panel: Color::RGBA(0, 0, 0, 230),
+            background_dim: 196,
+            playfield_x: 175,
+            playfield_width: 400,
+            score_position: (15, 15),
+            mode_position: (15, 60),
+            modifiers_position: (15, 105),
+            combo_color: Color::RGBA(255, 255, 255, 128),
+            combo_distance: 100.0,
+            judgment_distance: 170.0,
+            error_bar_distance: 135.0,
+            difficulty_title_y: 50,
+            difficulty_position: (250, 150),
+            difficulty_size: (300, 60),
+            difficulty_spacing: 80,
+        }
+    }
+}
+
+
+impl Theme {
+    // Reads `dir/theme.txt`. Paths in it are relative to `dir`.
+    pub fn load(dir: &Path) -> Self {
+        let mut theme = Self::default();
+        let Some(tags) = config::read_tags(&dir.join("theme.txt")) else {
+            eprintln!("No theme in {}, using the default one", dir.display());
+            return theme;
+        };
+
+        for (tag, value) in tags {
+            let ok = match tag.as_str() {
+                "FONT" => {
+                    theme.font = dir.join(&value);
+                    true
+                }
+                "FONTSIZE" => parse(&value, &mut theme.font_size),
+                "SMALLFONTSIZE" => parse(&value, &mut theme.small_font_size),
+                "TEXTCOLOR" => parse_color(&value, &mut theme.text),
+                "SUBTEXTCOLOR" => parse_color(&value, &mut theme.subtext),
+                "ACCENTCOLOR" => parse_color(&value, &mut theme.accent),
+                "SELECTEDCOLOR" => parse_color(&value, &mut theme.selected),
+                "UNSELECTEDCOLOR" => parse_color(&value, &mut theme.unselected),
+                "MENUBACKGROUND" => parse_color(&value, &mut theme.menu_background),
+                "LISTITEMCOLOR" => parse_color(&value, &mut theme.list_item),
+                "PANELCOLOR" => parse_color(&value, &mut theme.panel),
+                "BACKGROUNDDIM" => parse(&value, &mut theme.background_dim),
+                "PLAYFIELDX" => parse(&value, &mut theme.playfield_x),
+                "PLAYFIELDWIDTH" => parse(&value, &mut theme.playfield_width),
+                "SCOREPOSITION" => parse_pair(&value, &mut theme.score_position),
+                "MODEPOSITION" => parse_pair(&value, &mut theme.mode_position),
+                "MODIFIERSPOSITION" => parse_pair(&value, &mut theme.modifiers_position),
+                "COMBOCOLOR" => parse_color(&value, &mut theme.combo_color),
+                "COMBODISTANCE" => parse(&value, &mut theme.combo_distance),
+                "JUDGMENTDISTANCE" => parse(&value, &mut theme.judgment_distance),
+                "ERRORBARDISTANCE" => parse(&value, &mut theme.error_bar_distance),
+                "DIFFICULTYTITLEY" => parse(&value, &mut theme.difficulty_title_y),
+                "DIFFICULTYPOSITION" => parse_pair(&value, &mut theme.difficulty_position),
+                "DIFFICULTYSIZE" => parse_pair(&value, &mut theme.difficulty_size),
+                "DIFFICULTYSPACING" => parse(&value, &mut theme.difficulty_spacing),
+                _ => false,
+            };
+            if !ok {
+                eprintln!("Ignoring #{}:{}; in {}", tag, value, dir.display());
+            }
+        }
+
+        if !theme.font.is_file() {
+            eprintln!("No font at {}, using the default one", theme.font.display());
+            theme.font = Self::default().font;
+        }
+        theme
+    }
+}
+
+
+fn parse<T: std::str::FromStr>(value: &str, field: &mut T) -> bool {
+    value.trim().parse().map(|parsed| *field = parsed).is_ok()
+}
+
+
+// "x,y"
+fn parse_pair<T: std::str::FromStr>(value: &str, field: &mut (T, T)) -> bool {
+    let Some((a, b)) = value.split_once(',') else {
+        return false;
+    };
+    match (a.trim().parse(), b.trim().parse()) {
+        (Ok(a), Ok(b)) => {
+            *field = (a, b);
+            true
+        }
+        _ => false,
+    }
+}
+
+
+// "r,g,b" or "r,g,b,a"
+fn parse_color(value: &str, field: &mut Color) -> bool {
+    let parts: Option<Vec<u8>> = value.split(',').map(|part| part.trim().parse().ok()).collect();
+    match parts.as_deref() {
+        Some(&[r, g, b]) => *field = Color::RGB(r, g, b),
+        Some(&[r, g, b, a]) => *field = Color::RGBA(r, g, b, a),
+        _ => return false,
+    }
+    true
+}
@@ -0,0 +1,165 @@
+// Rows of the note grid in a beat. A 4/4 measure is 192 rows, which fits every
+// subdivision simfiles use (4ths to 192nds) exactly.
+pub const ROWS_PER_BEAT: u32 = 48;
+pub const ROWS_PER_MEASURE: u32 = 4 * ROWS_PER_BEAT;
+
+
+// Row of line `line` out of `lines` in measure `measure`. Measures split into a count
+// that doesn't divide 192 are rounded down to the nearest row.
+pub fn measure_row(measure: u32, line: usize, lines: usize) -> u32 {
+    measure * ROWS_PER_MEASURE + (line as u64 * ROWS_PER_MEASURE as u64 / lines as u64) as u32
+}
+
+
+pub fn row_beat(row: u32) -> f64 {
+    row as f64 / ROWS_PER_BEAT as f64
+}
+
+
+// Note value a row falls on: 4 for 4ths (on the beat), 8 for 8ths, 12 for 12ths... up to 192
+pub fn quantization(row: u32) -> u32 {
+    let offset = row % ROWS_PER_BEAT;
+    if offset == 0 {
+        return 4;
+    }
+    4 * ROWS_PER_BEAT / gcd(offset, ROWS_PER_BEAT)
+}
+
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+
+// (beat, value) pairs sorted by beat. A beat listed twice keeps its last value.
+fn by_beat(mut values: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+    values.dedup_by(|later, earlier| {
+        let same = later.0 == earlier.0;
+        if same {
+            *earlier = *later;
+        }
+        same
+    });
+    values
+}
+
+
+// Where beats fall in the music, from a simfile's #OFFSET, #BPMS and #STOPS.
+// Note times are always worked out from this, never stored on their own.
+#[derive(Debug, Clone, Default)]
+pub struct TimingData {
+    offset: f64,
+    bpms: Vec<(f64, f64)>, // (beat, bpm), sorted by beat
+    stops: Vec<(f64, f64)>, // (beat, seconds), sorted by beat
+}
+
+
+impl TimingData {
+    pub fn new(offset: f64, bpms: Vec<(f64, f64)>, stops: Vec<(f64, f64)>) -> Self {
+        Self { offset, bpms: by_beat(bpms), stops: by_beat(stops) }
+    }
+
+    // Seconds into the music at 1.0x
+    pub fn beat_time(&self, beat: f64) -> f64 {
+        let mut last_time = self.offset;
+        let mut last_beat = 0.0;
+        let mut last_bpm = 120.0;
+
+        for &(bpm_beat, bpm) in &self.bpms {
+            if beat < bpm_beat {
+                break;
+            }
+            last_time += (bpm_beat - last_beat) * (60.0 / last_bpm);
+            last_beat = bpm_beat;
+            last_bpm = bpm;
+        }
+
+        for &(stop_beat, stop_duration) in &self.stops {
+            if stop_beat <= beat {
+                last_time += stop_duration;
+            }
+        }
+
+        last_time + (beat - last_beat) * (60.0 / last_bpm)
+    }
+
+    pub fn row_time(&self, row: u32) -> f64 {
+        self.beat_time(row_beat(row))
+    }
+
+    pub fn min_bpm(&self) -> Option<f64> {
+        self.bpms.iter().map(|&(_, bpm)| bpm).reduce(f64::min)
+    }
+
+    pub fn max_bpm(&self) -> Option<f64> {
+        self.bpms.iter().map(|&(_, bpm)| bpm).reduce(f64::max)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measure_rows() {
+        assert_eq!(measure_row(0, 0, 4), 0);
+        assert_eq!(measure_row(0, 1, 4), 48);
+        assert_eq!(measure_row(1, 1, 4), 240);
+        assert_eq!(measure_row(0, 1, 8), 24);
+        assert_eq!(measure_row(0, 1, 12), 16);
+        assert_eq!(measure_row(0, 1, 192), 1);
+        // 5 doesn't divide 192, rounded down
+        assert_eq!(measure_row(0, 1, 5), 38);
+    }
+
+    #[test]
+    fn quantizations() {
+        assert_eq!(quantization(0), 4);
+        assert_eq!(quantization(ROWS_PER_MEASURE + 48), 4);
+        assert_eq!(quantization(24), 8);
+        assert_eq!(quantization(16), 12);
+        assert_eq!(quantization(32), 12);
+        assert_eq!(quantization(12), 16);
+        assert_eq!(quantization(8), 24);
+        assert_eq!(quantization(6), 32);
+        assert_eq!(quantization(4), 48);
+        assert_eq!(quantization(3), 64);
+        assert_eq!(quantization(1), 192);
+        assert_eq!(quantization(47), 192);
+    }
+
+    #[test]
+    fn constant_bpm() {
+        let timing = TimingData::new(0.25, vec![(0.0, 120.0)], Vec::new());
+        assert_eq!(timing.beat_time(0.0), 0.25);
+        assert_eq!(timing.beat_time(4.0), 2.25);
+        assert_eq!(timing.row_time(ROWS_PER_BEAT / 2), 0.5);
+    }
+
+    #[test]
+    fn bpm_changes() {
+        // Listed out of order, and beat 0 twice
+        let timing = TimingData::new(0.0, vec![(4.0, 240.0), (0.0, 60.0), (0.0, 120.0)], Vec::new());
+        assert_eq!(timing.beat_time(1.0), 0.5);
+        assert_eq!(timing.beat_time(4.0), 2.0);
+        assert_eq!(timing.beat_time(8.0), 3.0);
+        assert_eq!(timing.min_bpm(), Some(120.0));
+        assert_eq!(timing.max_bpm(), Some(240.0));
+    }
+
+    #[test]
+    fn stops() {
+        let timing = TimingData::new(0.0, vec![(0.0, 120.0)], vec![(2.0, 0.5)]);
+        assert_eq!(timing.beat_time(1.0), 0.5);
+        assert_eq!(timing.beat_time(2.0), 1.5);
+        assert_eq!(timing.beat_time(3.0), 2.0);
+    }
+
+    #[test]
+    fn stop_listed_twice_keeps_the_last() {
+        let timing = TimingData::new(0.0, vec![(0.0, 120.0)], vec![(2.0, 0.5), (2.0, 0.25)]);
+        assert_eq!(timing.beat_time(3.0), 1.75);
+    }
+}